@@ -0,0 +1,242 @@
+//! Receives DMX universes as ArtDmx packets and answers ArtPoll, so the client
+//! shows up as an Art-Net node in lighting consoles.
+
+use std::error::Error;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+use crate::dmx::{self, Universes};
+use crate::source::{ParseError, Pixels, Received, Source, Update};
+use crate::stats::Report;
+
+pub const PORT: u16 = 6454;
+
+const ID: &[u8; 8] = b"Art-Net\0";
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+const DMX_HEADER_LEN: usize = 18;
+const MAX_PACKET_LEN: usize = DMX_HEADER_LEN + 512;
+const POLL_REPLY_LEN: usize = 239;
+/// Ports described by a single ArtPollReply.
+const PORTS_PER_REPLY: usize = 4;
+const SHORT_NAME: &str = "schatter";
+const LONG_NAME: &str = "schatter-client";
+
+#[derive(Debug, PartialEq)]
+pub enum Packet<'a> {
+    Poll,
+    Dmx(DmxPacket<'a>),
+}
+
+/// The fields of an ArtDmx packet the client cares about.
+#[derive(Debug, PartialEq)]
+pub struct DmxPacket<'a> {
+    /// The 15 bit port address made of net, sub-net and universe.
+    pub universe: u16,
+    /// 0 if the sender does not number its packets.
+    pub sequence: u8,
+    pub channels: &'a [u8],
+}
+
+/// Parses ArtPoll and ArtDmx packets. Every other opcode returns `None`.
+pub fn parse(bytes: &[u8]) -> Result<Option<Packet<'_>>, ParseError> {
+    if bytes.len() < 10 || &bytes[0..8] != ID {
+        return Err(ParseError("not an Art-Net packet"));
+    }
+    match u16::from_le_bytes([bytes[8], bytes[9]]) {
+        OP_POLL => Ok(Some(Packet::Poll)),
+        OP_DMX => {
+            if bytes.len() < DMX_HEADER_LEN {
+                return Err(ParseError("ArtDmx packet too short"));
+            }
+            let len = u16::from_be_bytes([bytes[16], bytes[17]]) as usize;
+            if len > 512 || DMX_HEADER_LEN + len > bytes.len() {
+                return Err(ParseError("ArtDmx length exceeds packet"));
+            }
+            Ok(Some(Packet::Dmx(DmxPacket {
+                universe: u16::from_le_bytes([bytes[14], bytes[15]]) & 0x7fff,
+                sequence: bytes[12],
+                channels: &bytes[DMX_HEADER_LEN..DMX_HEADER_LEN + len],
+            })))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Builds an ArtPollReply describing up to four output ports. All universes
+/// have to share the same net and sub-net.
+pub fn poll_reply(ip: Ipv4Addr, universes: &[u16], bind_index: u8, polls: u16) -> Vec<u8> {
+    let mut bytes = vec![0; POLL_REPLY_LEN];
+    bytes[0..8].copy_from_slice(ID);
+    bytes[8..10].copy_from_slice(&OP_POLL_REPLY.to_le_bytes());
+    bytes[10..14].copy_from_slice(&ip.octets());
+    bytes[14..16].copy_from_slice(&PORT.to_le_bytes());
+    let address = universes.first().copied().unwrap_or_default();
+    bytes[18] = (address >> 8) as u8 & 0x7f;
+    bytes[19] = (address >> 4) as u8 & 0x0f;
+    // Indicators normal, port addresses set from the configuration.
+    bytes[23] = 0xd0;
+    copy_str(&mut bytes[26..44], SHORT_NAME);
+    copy_str(&mut bytes[44..108], LONG_NAME);
+    copy_str(
+        &mut bytes[108..172],
+        &format!("#0001 [{:04}] Power On Tests successful", polls % 10000),
+    );
+    bytes[173] = universes.len() as u8;
+    for (i, universe) in universes.iter().enumerate() {
+        // Outputs DMX512 received over Art-Net.
+        bytes[174 + i] = 0x80;
+        bytes[182 + i] = 0x80;
+        bytes[190 + i] = *universe as u8 & 0x0f;
+    }
+    bytes[207..211].copy_from_slice(&ip.octets());
+    bytes[211] = bind_index;
+    // Supports 15 bit port addresses.
+    bytes[212] = 0x08;
+    bytes
+}
+
+fn copy_str(field: &mut [u8], value: &str) {
+    // Leave room for the terminating zero.
+    let len = value.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&value.as_bytes()[..len]);
+}
+
+pub struct ArtNetSource {
+    socket: UdpSocket,
+    outputs: Vec<Universes>,
+    sequences: Vec<Vec<Option<u8>>>,
+    buffer: Vec<u8>,
+    stale: Vec<u64>,
+    polls: u16,
+}
+
+impl ArtNetSource {
+    pub fn bind(port: u16, outputs: Vec<Universes>) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        socket.set_nonblocking(true)?;
+        socket.set_broadcast(true)?;
+        Ok(ArtNetSource {
+            socket,
+            sequences: dmx::sequences(&outputs),
+            stale: vec![0; outputs.len()],
+            outputs,
+            buffer: vec![0; MAX_PACKET_LEN],
+            polls: 0,
+        })
+    }
+
+    /// Answers an ArtPoll with one reply per group of ports sharing a net and
+    /// sub-net, as the strips usually span more universes than fit in one
+    /// reply.
+    fn reply(&mut self, controller: SocketAddr) -> io::Result<()> {
+        let ip = local_ip(controller)?;
+        let universes: Vec<u16> = self.outputs.iter().flat_map(Universes::universes).collect();
+        let groups = universes.chunk_by(|a, b| a >> 4 == b >> 4);
+        let mut bind_index = 1;
+        for group in groups {
+            for ports in group.chunks(PORTS_PER_REPLY) {
+                let reply = poll_reply(ip, ports, bind_index, self.polls);
+                self.socket.send_to(&reply, (controller.ip(), PORT))?;
+                bind_index = bind_index.wrapping_add(1);
+            }
+        }
+        self.polls = self.polls.wrapping_add(1);
+        Ok(())
+    }
+}
+
+impl Source for ArtNetSource {
+    fn poll(&mut self) -> Result<Option<Update>, Box<dyn Error>> {
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let packet = match parse(&self.buffer[..len])? {
+                Some(Packet::Dmx(packet)) => packet,
+                Some(Packet::Poll) => {
+                    self.reply(from)?;
+                    continue;
+                }
+                None => continue,
+            };
+            let Some(output) = dmx::find(&self.outputs, packet.universe) else {
+                continue;
+            };
+            let universes = &mut self.outputs[output];
+            let index = packet.universe.wrapping_sub(universes.start()) as usize;
+            let last = &mut self.sequences[output][index];
+            if packet.sequence != 0 && dmx::is_stale(last, packet.sequence) {
+                self.stale[output] += 1;
+                continue;
+            }
+            if let Some(leds) = universes.push(packet.universe, packet.channels) {
+                return Ok(Some(Update::Frame(Received {
+                    output,
+                    sequence: None,
+                    present_at: None,
+                    rendered_at: None,
+                    pixels: Pixels::Rgb8(leds),
+                })));
+            }
+        }
+    }
+
+    fn extend_report(&self, output: usize, report: &mut Report) {
+        report.reordered = self.stale[output];
+    }
+}
+
+/// The address of the interface the controller is reached through.
+fn local_ip(controller: SocketAddr) -> io::Result<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(controller)?;
+    match socket.local_addr()?.ip() {
+        IpAddr::V4(ip) => Ok(ip),
+        IpAddr::V6(_) => Ok(Ipv4Addr::UNSPECIFIED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROTOCOL_VERSION: u16 = 14;
+
+    #[test]
+    fn parse_dmx_packet() {
+        let mut bytes = ID.to_vec();
+        bytes.extend_from_slice(&OP_DMX.to_le_bytes());
+        bytes.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&[5, 0, 0x21, 0x01, 0, 4, 1, 2, 3, 4]);
+        assert_eq!(
+            parse(&bytes),
+            Ok(Some(Packet::Dmx(DmxPacket {
+                universe: 0x121,
+                sequence: 5,
+                channels: &[1, 2, 3, 4],
+            })))
+        );
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut poll = ID.to_vec();
+        poll.extend_from_slice(&OP_POLL.to_le_bytes());
+        poll.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        poll.extend_from_slice(&[0, 0]);
+        assert_eq!(parse(&poll), Ok(Some(Packet::Poll)));
+    }
+
+    #[test]
+    fn describe_ports_in_poll_reply() {
+        let reply = poll_reply(Ipv4Addr::new(10, 0, 0, 2), &[0x121, 0x122], 1, 0);
+        assert_eq!(reply.len(), POLL_REPLY_LEN);
+        assert_eq!(&reply[10..14], &[10, 0, 0, 2]);
+        assert_eq!((reply[18], reply[19]), (0x01, 0x02));
+        assert_eq!(reply[173], 2);
+        assert_eq!(&reply[190..194], &[1, 2, 0, 0]);
+        assert_eq!(&reply[26..34], SHORT_NAME.as_bytes());
+    }
+}
@@ -0,0 +1,190 @@
+//! Tracks the offset of the server's clock from the client's, so frames are
+//! shown at the time the server stamped them with and the strips of all
+//! clients change together.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use schatter_lib::auth::Key;
+use schatter_lib::clock::{SyncPacket, TAGGED_LEN};
+use schatter_lib::protocol::SYNC_PORT;
+
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+/// Replies the offset is estimated from.
+const SAMPLES: usize = 8;
+
+pub struct Clock {
+    socket: UdpSocket,
+    interval: Duration,
+    /// Replies are dropped unless they are tagged with this key.
+    key: Option<Key>,
+    /// The server the socket is connected to, so it only receives its
+    /// replies.
+    server: Option<IpAddr>,
+    epoch: Instant,
+    last_request: Option<Instant>,
+    /// The origin of the request a reply is awaited to, so replies to
+    /// earlier requests and replayed ones are ignored.
+    pending: Option<u64>,
+    /// Offset and round trip time in µs of the last replies.
+    samples: VecDeque<(i64, u64)>,
+    /// The server's clock minus the client's, from the sample with the
+    /// shortest round trip, which queueing delayed the least.
+    offset: Option<i64>,
+    last_warning: Option<Instant>,
+}
+
+impl Clock {
+    pub fn new(interval: Duration, key: Option<Key>) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_nonblocking(true)?;
+        Ok(Clock {
+            socket,
+            interval,
+            key,
+            server: None,
+            epoch: Instant::now(),
+            last_request: None,
+            pending: None,
+            samples: VecDeque::new(),
+            offset: None,
+            last_warning: None,
+        })
+    }
+
+    /// Asks `server` for its time when a request is due and reads the
+    /// replies which arrived.
+    pub fn poll(&mut self, server: IpAddr) {
+        if self.server != Some(server) {
+            if let Err(e) = self.socket.connect((server, SYNC_PORT)) {
+                return self.warn(e);
+            }
+            // The offset to another server's clock says nothing about this
+            // one's.
+            self.server = Some(server);
+            self.last_request = None;
+            self.pending = None;
+            self.samples.clear();
+            self.offset = None;
+        }
+        if self
+            .last_request
+            .is_none_or(|last| last.elapsed() >= self.interval)
+        {
+            self.last_request = Some(Instant::now());
+            let origin = self.now();
+            self.pending = Some(origin);
+            if let Err(e) = self.socket.send(&SyncPacket::request(origin).encode()) {
+                self.warn(e);
+            }
+        }
+        let mut packet = [0; TAGGED_LEN + 1];
+        loop {
+            match self.socket.recv(&mut packet) {
+                Ok(len) => self.receive(&packet[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => return self.warn(e),
+            }
+        }
+    }
+
+    pub fn is_synchronised(&self) -> bool {
+        self.offset.is_some()
+    }
+
+    /// The local time a time on the server's clock corresponds to, once the
+    /// clocks are synchronised.
+    pub fn local_time(&self, server_time: u64) -> Option<Instant> {
+        let local = server_time as i64 - self.offset?;
+        Some(self.epoch + Duration::from_micros(u64::try_from(local).ok()?))
+    }
+
+    /// The time which passed since a time on the server's clock, once the
+    /// clocks are synchronised.
+    pub fn elapsed_since(&self, server_time: u64) -> Option<Duration> {
+        let now = self.now() as i64 + self.offset?;
+        let elapsed = u64::try_from(now - server_time as i64).unwrap_or(0);
+        Some(Duration::from_micros(elapsed))
+    }
+
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    /// Takes a sample from a reply to the pending request.
+    fn receive(&mut self, bytes: &[u8]) {
+        let Some(reply) = SyncPacket::decode_tagged(bytes, self.key.as_ref()) else {
+            return;
+        };
+        if self.pending == Some(reply.origin) {
+            self.pending = None;
+            self.add_sample(reply.offset_and_delay(self.now()));
+        }
+    }
+
+    fn add_sample(&mut self, sample: (i64, u64)) {
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        let best = self.samples.iter().min_by_key(|(_, delay)| *delay).copied();
+        if let (None, Some((offset, delay))) = (self.offset, best) {
+            println!(
+                "Clock synchronised with the server, offset {:.1} ms, round trip {:.1} ms",
+                offset as f64 / 1000.0,
+                delay as f64 / 1000.0
+            );
+        }
+        self.offset = best.map(|(offset, _)| offset);
+    }
+
+    fn warn(&mut self, error: io::Error) {
+        if self
+            .last_warning
+            .is_none_or(|last| last.elapsed() >= WARNING_INTERVAL)
+        {
+            self.last_warning = Some(Instant::now());
+            println!("Warning: could not synchronise the clock: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trust_the_shortest_round_trip() {
+        let mut clock = Clock::new(Duration::from_secs(1), None).unwrap();
+        assert_eq!(clock.local_time(5_000_000), None);
+        clock.add_sample((4_000_000, 900));
+        clock.add_sample((3_000_000, 100));
+        clock.add_sample((2_000_000, 500));
+        let local = clock.local_time(5_000_000).unwrap();
+        assert_eq!(local - clock.epoch, Duration::from_secs(2));
+        assert_eq!(clock.local_time(1_000_000), None);
+    }
+
+    #[test]
+    fn accept_only_tagged_replies_to_the_pending_request() {
+        let key = Key::new(b"gallery");
+        let mut clock = Clock::new(Duration::from_secs(1), Some(key.clone())).unwrap();
+        let reply = |origin| SyncPacket {
+            origin,
+            receive: 5_000_000,
+            transmit: 5_000_000,
+        };
+        clock.receive(&reply(10).encode_tagged(Some(&key)));
+        assert!(!clock.is_synchronised());
+        clock.pending = Some(20);
+        clock.receive(&reply(10).encode_tagged(Some(&key)));
+        clock.receive(&reply(20).encode_tagged(None));
+        clock.receive(&reply(20).encode_tagged(Some(&Key::new(b"guess"))));
+        assert!(!clock.is_synchronised());
+        clock.receive(&reply(20).encode_tagged(Some(&key)));
+        assert!(clock.is_synchronised());
+        assert_eq!(clock.pending, None);
+    }
+}
@@ -0,0 +1,821 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+
+use crate::ddp;
+use crate::dmx;
+use crate::osc;
+use crate::pattern::Pattern;
+use crate::remap::Remap;
+use crate::sink::SinkKind;
+
+/// GPIO pins the ws281x driver can generate the data signal on.
+const VALID_PINS: [i32; 6] = [10, 12, 13, 18, 19, 21];
+const MAX_DMA: i32 = 14;
+const MAX_GAMMA: f32 = 5.0;
+const MAX_REFRESH_HZ: u32 = 1000;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Drives the monolith's LED strips from a Raspberry Pi"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// TOML file with the client configuration, overridden by the options below
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub overrides: Overrides,
+}
+
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Show a test pattern
+    Test,
+    /// Show the frames streamed by the server
+    Stream,
+    /// Show DMX universes received over sACN (E1.31)
+    E131,
+    /// Show DMX universes received over Art-Net
+    #[command(name = "artnet")]
+    ArtNet,
+    /// Show pixel data received over DDP
+    Ddp,
+    /// Show pixel data received over Open Pixel Control
+    Opc,
+    /// Show the frames of this client in a recording of the server, in a loop
+    Replay,
+}
+
+#[derive(Args, Debug, Default)]
+pub struct Overrides {
+    /// Port to receive frames on [default: the standard port of the protocol]
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+
+    /// GPIO pin the strip's data line is connected to
+    #[arg(long, global = true)]
+    pub pin: Option<i32>,
+
+    /// DMA channel used by the ws281x driver
+    #[arg(long, global = true)]
+    pub dma: Option<i32>,
+
+    /// Number of LEDs on the strip [default: taken from the first frame when streaming]
+    #[arg(long, global = true)]
+    pub num_leds: Option<usize>,
+
+    /// Order in which the strip expects the color channels
+    #[arg(long, global = true, value_enum)]
+    pub color_order: Option<ColorOrder>,
+
+    /// Global brightness, 0-255
+    #[arg(long, global = true)]
+    pub brightness: Option<u8>,
+
+    /// Exponent of the gamma curve applied to every color, 1 to write frames unchanged
+    #[arg(long, global = true)]
+    pub gamma: Option<f32>,
+
+    /// White balance as gain of the red, green and blue channel, each 0-1
+    #[arg(long, global = true, value_parser = parse_rgb, value_name = "R,G,B")]
+    pub gain: Option<[f32; 3]>,
+
+    /// Spread the rounding of 16 bit frames to the LEDs' 8 bits over time
+    #[arg(long, global = true)]
+    pub dither: Option<bool>,
+
+    /// Current an LED draws per color channel at full brightness
+    #[arg(long, global = true, value_parser = parse_rgb, value_name = "R,G,B")]
+    pub milliamps: Option<[f32; 3]>,
+
+    /// Current budget of a strip in mA, brightness is scaled down to stay within it, 0 for none
+    #[arg(long, global = true)]
+    pub max_milliamps: Option<u32>,
+
+    /// Where to write the frames to: ws281x, terminal, null or file:<path>
+    #[arg(long, global = true)]
+    pub sink: Option<SinkKind>,
+
+    /// Milliseconds without a frame before the strip fades out, 0 to never time out
+    #[arg(long, global = true)]
+    pub timeout_ms: Option<u64>,
+
+    /// Milliseconds the fade out takes once the stream timed out
+    #[arg(long, global = true)]
+    pub fade_ms: Option<u64>,
+
+    /// What to show once the strip faded out
+    #[arg(long, global = true, value_enum)]
+    pub timeout_action: Option<TimeoutAction>,
+
+    /// Show frames at a fixed rate, holding or blending between the received ones
+    #[arg(long, global = true, value_enum)]
+    pub interpolation: Option<Interpolation>,
+
+    /// Frames per second written to the strip when interpolating
+    #[arg(long, global = true)]
+    pub refresh_hz: Option<u32>,
+
+    /// Milliseconds frames are delayed by when interpolating, to absorb network jitter
+    #[arg(long, global = true)]
+    pub latency_ms: Option<u64>,
+
+    /// Test pattern to show
+    #[arg(long, global = true, value_enum)]
+    pub pattern: Option<Pattern>,
+
+    /// Speed of the test pattern, 1 for its normal speed
+    #[arg(long, global = true)]
+    pub pattern_speed: Option<f32>,
+
+    /// Test pattern shown until a stream arrives and after it timed out, with the fallback timeout action
+    #[arg(long, global = true, value_enum)]
+    pub fallback_pattern: Option<Pattern>,
+
+    /// Speed of the fallback pattern, 1 for its normal speed
+    #[arg(long, global = true)]
+    pub fallback_speed: Option<f32>,
+
+    /// Address of the server status messages are sent to, as <host>:<port>
+    #[arg(long, global = true)]
+    pub server: Option<String>,
+
+    /// Name the client announces itself and reports its status with [default: the host name]
+    #[arg(long, global = true)]
+    pub client_id: Option<String>,
+
+    /// Secret shared with the server, OSC messages without its tag are dropped
+    #[arg(long, global = true)]
+    pub secret: Option<String>,
+
+    /// Recording of the server to replay, its streams <client id>:<output> are shown on the outputs
+    #[arg(long, global = true)]
+    pub recording: Option<PathBuf>,
+
+    /// Milliseconds between status messages to the server, 0 to disable them
+    #[arg(long, global = true)]
+    pub status_interval_ms: Option<u64>,
+
+    /// Milliseconds between broadcasts announcing the client to the server, 0 to disable them
+    #[arg(long, global = true)]
+    pub announce_interval_ms: Option<u64>,
+
+    /// Milliseconds between clock synchronisations with the server, 0 to show frames on arrival
+    #[arg(long, global = true)]
+    pub sync_interval_ms: Option<u64>,
+
+    /// Milliseconds between printed stream statistics, 0 to disable them
+    #[arg(long, global = true)]
+    pub stats_interval_ms: Option<u64>,
+
+    /// First DMX universe mapped onto the strip
+    #[arg(long, global = true)]
+    pub universe: Option<u16>,
+
+    /// DMX channels to skip in the first universe
+    #[arg(long, global = true)]
+    pub channel_offset: Option<usize>,
+
+    /// OPC channel of the strip, 1-255
+    #[arg(long, global = true)]
+    pub opc_channel: Option<u8>,
+
+    /// Drive several strips from one process, each given as <port>:<pin>:<dma>
+    #[arg(long = "output", global = true, value_name = "PORT:PIN:DMA")]
+    pub outputs: Vec<OutputConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorOrder {
+    #[default]
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutAction {
+    /// Keep the strip dark
+    #[default]
+    Blank,
+    /// Show a dim breathing pattern
+    Idle,
+    /// Show the fallback test pattern, also until the first stream arrives
+    Fallback,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    /// Write every frame as soon as it arrived
+    #[default]
+    Off,
+    /// Show the last frame until the next one is due
+    Hold,
+    /// Blend linearly between the last two frames
+    Linear,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub port: Option<u16>,
+    pub pin: i32,
+    pub dma: i32,
+    pub num_leds: Option<usize>,
+    pub color_order: ColorOrder,
+    pub brightness: u8,
+    pub gamma: f32,
+    pub gain: [f32; 3],
+    pub dither: bool,
+    pub milliamps: [f32; 3],
+    pub max_milliamps: u32,
+    pub sink: SinkKind,
+    pub timeout_ms: u64,
+    pub fade_ms: u64,
+    pub timeout_action: TimeoutAction,
+    pub interpolation: Interpolation,
+    pub refresh_hz: u32,
+    pub latency_ms: u64,
+    pub pattern: Pattern,
+    pub pattern_speed: f32,
+    pub fallback_pattern: Pattern,
+    pub fallback_speed: f32,
+    /// Index of the first LED of every stripe of the strip after the first,
+    /// shown by the markers test pattern.
+    pub stripes: Vec<usize>,
+    pub remap: Remap,
+    pub server: Option<String>,
+    pub client_id: Option<String>,
+    pub secret: Option<String>,
+    pub recording: Option<PathBuf>,
+    pub status_interval_ms: u64,
+    pub announce_interval_ms: u64,
+    pub sync_interval_ms: u64,
+    pub stats_interval_ms: u64,
+    pub universe: u16,
+    pub channel_offset: usize,
+    pub opc_channel: u8,
+    /// Strips driven by this process. Empty for a single strip described by
+    /// the values above.
+    pub outputs: Vec<OutputConfig>,
+}
+
+/// A strip driven next to others by the same process. Every value which is
+/// not given falls back to the top level one.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub port: Option<u16>,
+    pub pin: Option<i32>,
+    pub dma: Option<i32>,
+    pub num_leds: Option<usize>,
+    /// Defaults to the universe following the previous strip.
+    pub universe: Option<u16>,
+    /// Defaults to the position of the output, starting at 1.
+    pub opc_channel: Option<u8>,
+    /// Current budget of the power supply feeding this strip.
+    pub max_milliamps: Option<u32>,
+    pub stripes: Option<Vec<usize>>,
+    pub remap: Option<Remap>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            port: None,
+            pin: 18,
+            dma: 10,
+            num_leds: None,
+            color_order: ColorOrder::default(),
+            brightness: 255,
+            gamma: 1.0,
+            gain: [1.0; 3],
+            dither: true,
+            milliamps: [20.0; 3],
+            max_milliamps: 0,
+            sink: SinkKind::default(),
+            timeout_ms: 2000,
+            fade_ms: 1000,
+            timeout_action: TimeoutAction::default(),
+            interpolation: Interpolation::default(),
+            refresh_hz: 50,
+            latency_ms: 50,
+            pattern: Pattern::default(),
+            pattern_speed: 1.0,
+            fallback_pattern: Pattern::Rainbow,
+            fallback_speed: 1.0,
+            stripes: Vec::new(),
+            remap: Remap::default(),
+            server: None,
+            client_id: None,
+            secret: None,
+            recording: None,
+            status_interval_ms: 5000,
+            announce_interval_ms: 2000,
+            sync_interval_ms: 1000,
+            stats_interval_ms: 10000,
+            universe: 1,
+            channel_offset: 0,
+            opc_channel: 1,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+/// Parses one value for each color channel, given as `<r>,<g>,<b>`.
+fn parse_rgb(s: &str) -> Result<[f32; 3], String> {
+    let values: Vec<f32> = s
+        .split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid number '{}'", value))
+        })
+        .collect::<Result<_, _>>()?;
+    values
+        .try_into()
+        .map_err(|_| format!("expected a value for red, green and blue, got '{}'", s))
+}
+
+impl FromStr for OutputConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid output '{}', expected <port>:<pin>:<dma>", s);
+        let fields: Vec<&str> = s.split(':').collect();
+        let [port, pin, dma] = fields[..] else {
+            return Err(invalid());
+        };
+        Ok(OutputConfig {
+            port: Some(port.parse().map_err(|_| invalid())?),
+            pin: Some(pin.parse().map_err(|_| invalid())?),
+            dma: Some(dma.parse().map_err(|_| invalid())?),
+            ..OutputConfig::default()
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            ConfigError::Invalid(message) => write!(f, "invalid configuration: {}", message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    /// Builds the configuration from the defaults, the optional config file
+    /// and the command line, in increasing order of precedence.
+    pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        config.apply(&cli.overrides);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let content =
+            fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn apply(&mut self, overrides: &Overrides) {
+        if let Some(port) = overrides.port {
+            self.port = Some(port);
+        }
+        if let Some(pin) = overrides.pin {
+            self.pin = pin;
+        }
+        if let Some(dma) = overrides.dma {
+            self.dma = dma;
+        }
+        if let Some(num_leds) = overrides.num_leds {
+            self.num_leds = Some(num_leds);
+        }
+        if let Some(color_order) = overrides.color_order {
+            self.color_order = color_order;
+        }
+        if let Some(brightness) = overrides.brightness {
+            self.brightness = brightness;
+        }
+        if let Some(gamma) = overrides.gamma {
+            self.gamma = gamma;
+        }
+        if let Some(gain) = overrides.gain {
+            self.gain = gain;
+        }
+        if let Some(dither) = overrides.dither {
+            self.dither = dither;
+        }
+        if let Some(milliamps) = overrides.milliamps {
+            self.milliamps = milliamps;
+        }
+        if let Some(max_milliamps) = overrides.max_milliamps {
+            self.max_milliamps = max_milliamps;
+        }
+        if let Some(sink) = &overrides.sink {
+            self.sink = sink.clone();
+        }
+        if let Some(timeout_ms) = overrides.timeout_ms {
+            self.timeout_ms = timeout_ms;
+        }
+        if let Some(fade_ms) = overrides.fade_ms {
+            self.fade_ms = fade_ms;
+        }
+        if let Some(timeout_action) = overrides.timeout_action {
+            self.timeout_action = timeout_action;
+        }
+        if let Some(interpolation) = overrides.interpolation {
+            self.interpolation = interpolation;
+        }
+        if let Some(refresh_hz) = overrides.refresh_hz {
+            self.refresh_hz = refresh_hz;
+        }
+        if let Some(latency_ms) = overrides.latency_ms {
+            self.latency_ms = latency_ms;
+        }
+        if let Some(pattern) = overrides.pattern {
+            self.pattern = pattern;
+        }
+        if let Some(pattern_speed) = overrides.pattern_speed {
+            self.pattern_speed = pattern_speed;
+        }
+        if let Some(fallback_pattern) = overrides.fallback_pattern {
+            self.fallback_pattern = fallback_pattern;
+        }
+        if let Some(fallback_speed) = overrides.fallback_speed {
+            self.fallback_speed = fallback_speed;
+        }
+        if let Some(server) = &overrides.server {
+            self.server = Some(server.clone());
+        }
+        if let Some(client_id) = &overrides.client_id {
+            self.client_id = Some(client_id.clone());
+        }
+        if let Some(secret) = &overrides.secret {
+            self.secret = Some(secret.clone());
+        }
+        if let Some(recording) = &overrides.recording {
+            self.recording = Some(recording.clone());
+        }
+        if let Some(status_interval_ms) = overrides.status_interval_ms {
+            self.status_interval_ms = status_interval_ms;
+        }
+        if let Some(announce_interval_ms) = overrides.announce_interval_ms {
+            self.announce_interval_ms = announce_interval_ms;
+        }
+        if let Some(sync_interval_ms) = overrides.sync_interval_ms {
+            self.sync_interval_ms = sync_interval_ms;
+        }
+        if let Some(stats_interval_ms) = overrides.stats_interval_ms {
+            self.stats_interval_ms = stats_interval_ms;
+        }
+        if let Some(universe) = overrides.universe {
+            self.universe = universe;
+        }
+        if let Some(channel_offset) = overrides.channel_offset {
+            self.channel_offset = channel_offset;
+        }
+        if let Some(opc_channel) = overrides.opc_channel {
+            self.opc_channel = opc_channel;
+        }
+        if !overrides.outputs.is_empty() {
+            self.outputs = overrides.outputs.clone();
+        }
+    }
+
+    /// The configuration of every strip, with the values of the outputs
+    /// merged into the top level ones.
+    pub fn outputs(&self) -> Vec<Config> {
+        if self.outputs.is_empty() {
+            return vec![self.clone()];
+        }
+        let mut resolved: Vec<Config> = Vec::new();
+        for (index, output) in self.outputs.iter().enumerate() {
+            let universe = match resolved.last() {
+                Some(previous) => previous.universe.wrapping_add(dmx::universe_count(
+                    previous.channel_offset,
+                    previous.num_leds.unwrap_or_default(),
+                ) as u16),
+                None => self.universe,
+            };
+            resolved.push(Config {
+                port: output.port.or(self.port),
+                pin: output.pin.unwrap_or(self.pin),
+                dma: output.dma.unwrap_or(self.dma),
+                num_leds: output.num_leds.or(self.num_leds),
+                universe: output.universe.unwrap_or(universe),
+                opc_channel: output.opc_channel.unwrap_or(index as u8 + 1),
+                max_milliamps: output.max_milliamps.unwrap_or(self.max_milliamps),
+                stripes: output
+                    .stripes
+                    .clone()
+                    .unwrap_or_else(|| self.stripes.clone()),
+                remap: output.remap.clone().unwrap_or_else(|| self.remap.clone()),
+                outputs: Vec::new(),
+                ..self.clone()
+            });
+        }
+        resolved
+    }
+
+    /// The ports of protocols which receive every output on its own port.
+    /// Outputs without a port of their own count up from `port`, or from
+    /// `default` if it is not set either.
+    pub fn own_ports(&self, default: u16) -> Result<Vec<u16>, ConfigError> {
+        let first = self.port.unwrap_or(default);
+        let mut ports: Vec<u16> = Vec::new();
+        for index in 0..self.outputs.len().max(1) {
+            let counted = u16::try_from(index)
+                .ok()
+                .and_then(|index| first.checked_add(index));
+            let own = self.outputs.get(index).and_then(|output| output.port);
+            let port = own.or(counted).ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "output {} needs a port, {} + {} is beyond the last one",
+                    index + 1,
+                    first,
+                    index
+                ))
+            })?;
+            if let Some(other) = ports.iter().position(|other| *other == port) {
+                return Err(ConfigError::Invalid(format!(
+                    "outputs {} and {} must not share port {}",
+                    other + 1,
+                    index + 1,
+                    port
+                )));
+            }
+            ports.push(port);
+        }
+        Ok(ports)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.outputs.is_empty() {
+            return self.validate_output();
+        }
+        for default in [osc::PORT, ddp::PORT] {
+            self.own_ports(default)?;
+        }
+        let outputs = self.outputs();
+        for (index, output) in outputs.iter().enumerate() {
+            output.validate_output()?;
+            let shared = outputs[..index]
+                .iter()
+                .find(|other| other.pin == output.pin || other.dma == output.dma);
+            if let Some(other) = shared {
+                return Err(ConfigError::Invalid(format!(
+                    "outputs on pin {} and pin {} must not share a pin or dma channel",
+                    other.pin, output.pin
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_output(&self) -> Result<(), ConfigError> {
+        if !VALID_PINS.contains(&self.pin) {
+            return Err(ConfigError::Invalid(format!(
+                "pin {} can not drive a strip, use one of {:?}",
+                self.pin, VALID_PINS
+            )));
+        }
+        if !(0..=MAX_DMA).contains(&self.dma) {
+            return Err(ConfigError::Invalid(format!(
+                "dma channel {} is out of range 0-{}",
+                self.dma, MAX_DMA
+            )));
+        }
+        if !(self.gamma > 0.0 && self.gamma <= MAX_GAMMA) {
+            return Err(ConfigError::Invalid(format!(
+                "gamma {} is out of range 0-{}",
+                self.gamma, MAX_GAMMA
+            )));
+        }
+        if self.gain.iter().any(|gain| !(0.0..=1.0).contains(gain)) {
+            return Err(ConfigError::Invalid(format!(
+                "gain {:?} is out of range, every channel must be within 0-1",
+                self.gain
+            )));
+        }
+        if self.milliamps.iter().any(|milliamps| *milliamps < 0.0) {
+            return Err(ConfigError::Invalid(format!(
+                "milliamps {:?} must not be negative",
+                self.milliamps
+            )));
+        }
+        if !(1..=MAX_REFRESH_HZ).contains(&self.refresh_hz) {
+            return Err(ConfigError::Invalid(format!(
+                "refresh_hz {} is out of range 1-{}",
+                self.refresh_hz, MAX_REFRESH_HZ
+            )));
+        }
+        if !(self.pattern_speed >= 0.0 && self.pattern_speed.is_finite()) {
+            return Err(ConfigError::Invalid(format!(
+                "pattern_speed {} must not be negative",
+                self.pattern_speed
+            )));
+        }
+        if !(self.fallback_speed >= 0.0 && self.fallback_speed.is_finite()) {
+            return Err(ConfigError::Invalid(format!(
+                "fallback_speed {} must not be negative",
+                self.fallback_speed
+            )));
+        }
+        if let Some([start, end]) = self.remap.reverse.iter().find(|[start, end]| start >= end) {
+            return Err(ConfigError::Invalid(format!(
+                "reversed range {}-{} of the remap table is empty",
+                start, end
+            )));
+        }
+        // A pixel in two ranges would only be reversed within the first one
+        // and shown twice.
+        let mut reverse = self.remap.reverse.clone();
+        reverse.sort_unstable();
+        if let Some(pair) = reverse.windows(2).find(|pair| pair[1][0] < pair[0][1]) {
+            return Err(ConfigError::Invalid(format!(
+                "reversed ranges {}-{} and {}-{} of the remap table overlap",
+                pair[0][0], pair[0][1], pair[1][0], pair[1][1]
+            )));
+        }
+        if self.num_leds == Some(0) {
+            return Err(ConfigError::Invalid("num_leds must not be 0".to_string()));
+        }
+        if self.channel_offset > 509 {
+            return Err(ConfigError::Invalid(format!(
+                "channel_offset {} leaves no room for an LED in the first universe",
+                self.channel_offset
+            )));
+        }
+        if self.opc_channel == 0 {
+            return Err(ConfigError::Invalid(
+                "opc_channel 0 addresses every strip, use 1-255".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_overrides_file() {
+        let mut config: Config = toml::from_str(
+            r#"
+            port = 34254
+            pin = 21
+            color_order = "grb"
+            sink = "null"
+            "#,
+        )
+        .unwrap();
+        let cli = Cli::parse_from([
+            "schatter-client",
+            "stream",
+            "--pin",
+            "18",
+            "--dma",
+            "9",
+            "--gain",
+            "1,0.9,0.8",
+        ]);
+        config.apply(&cli.overrides);
+
+        assert_eq!(cli.command, Command::Stream);
+        assert_eq!(config.port, Some(34254));
+        assert_eq!(config.pin, 18);
+        assert_eq!(config.dma, 9);
+        assert_eq!(config.color_order, ColorOrder::Grb);
+        assert_eq!(config.sink, SinkKind::Null);
+        assert_eq!(config.gain, [1.0, 0.9, 0.8]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn reject_invalid_values() {
+        let config = Config {
+            pin: 4,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        let config = Config {
+            dma: 15,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        let config = Config {
+            gamma: 0.0,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        let config = Config {
+            gain: [1.0, 1.5, 1.0],
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(toml::from_str::<Config>("speed = 3").is_err());
+    }
+
+    #[test]
+    fn resolve_outputs() {
+        let config: Config = toml::from_str(
+            r#"
+            num_leds = 626
+            universe = 1
+
+            [[outputs]]
+            pin = 18
+            dma = 9
+
+            [[outputs]]
+            pin = 21
+            "#,
+        )
+        .unwrap();
+        let outputs = config.outputs();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!((outputs[0].pin, outputs[0].dma), (18, 9));
+        assert_eq!((outputs[1].pin, outputs[1].dma), (21, 10));
+        assert_eq!((outputs[0].universe, outputs[1].universe), (1, 5));
+        assert_eq!((outputs[0].opc_channel, outputs[1].opc_channel), (1, 2));
+        assert!(config.validate().is_ok());
+
+        let cli = Cli::parse_from(["schatter-client", "stream", "--output", "1:18:9"]);
+        assert_eq!(cli.overrides.outputs[0].port, Some(1));
+        assert!(Cli::try_parse_from(["schatter-client", "stream", "--output", "1:18"]).is_err());
+        let shared = Config {
+            outputs: vec![OutputConfig::default(); 2],
+            ..Config::default()
+        };
+        assert!(shared.validate().is_err());
+    }
+
+    #[test]
+    fn reject_overlapping_reversed_ranges() {
+        let reversed = |reverse: Vec<[usize; 2]>| Config {
+            remap: Remap {
+                reverse,
+                ..Remap::default()
+            },
+            ..Config::default()
+        };
+        assert!(reversed(vec![[20, 30], [0, 10], [10, 20]])
+            .validate()
+            .is_ok());
+        assert!(reversed(vec![[20, 30], [0, 21]]).validate().is_err());
+        assert!(reversed(vec![[0, 30], [10, 20]]).validate().is_err());
+        assert!(reversed(vec![[5, 10], [5, 8]]).validate().is_err());
+    }
+
+    #[test]
+    fn reject_ports_beyond_the_last() {
+        let config = Config {
+            outputs: vec![OutputConfig::default(); 2],
+            ..Config::default()
+        };
+        assert_eq!(config.own_ports(7000).unwrap(), [7000, 7001]);
+        assert_eq!(config.own_ports(u16::MAX - 1).unwrap(), [65534, 65535]);
+        assert!(config.own_ports(u16::MAX).is_err());
+        let mut config = Config {
+            port: Some(7000),
+            ..config
+        };
+        assert_eq!(config.own_ports(u16::MAX).unwrap(), [7000, 7001]);
+        config.outputs[0].port = Some(7001);
+        assert!(config.own_ports(u16::MAX).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Receives pixel data over the Distributed Display Protocol, as sent by WLED
+//! and xLights. Packets fill a buffer at their offset and the buffer is shown
+//! once a packet with the push flag arrives.
+
+use std::error::Error;
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+
+use smart_leds::RGB8;
+
+use crate::source::{ParseError, Pixels, Received, Source, Update};
+
+pub const PORT: u16 = 4048;
+
+const VERSION_MASK: u8 = 0xc0;
+const VERSION_1: u8 = 0x40;
+const FLAG_TIMECODE: u8 = 0x10;
+const FLAG_QUERY: u8 = 0x02;
+const FLAG_PUSH: u8 = 0x01;
+const TYPE_UNDEFINED: u8 = 0x00;
+const TYPE_LEGACY_RGB: u8 = 0x01;
+const TYPE_RGB24: u8 = 0x0b;
+const ID_DISPLAY: u8 = 1;
+const ID_ALL: u8 = 255;
+const HEADER_LEN: usize = 10;
+const TIMECODE_LEN: usize = 4;
+const MAX_PACKET_LEN: usize = 1500;
+/// The largest buffer a sender can make the client allocate.
+const MAX_BYTES: usize = u16::MAX as usize * 3;
+
+/// The fields of a DDP data packet the client cares about.
+#[derive(Debug, PartialEq)]
+pub struct DataPacket<'a> {
+    pub push: bool,
+    /// Byte offset of the data in the display buffer.
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+/// Parses a packet writing RGB data to the display. Queries and packets for
+/// other destinations return `None`.
+pub fn parse(bytes: &[u8]) -> Result<Option<DataPacket<'_>>, ParseError> {
+    if bytes.len() < HEADER_LEN || bytes[0] & VERSION_MASK != VERSION_1 {
+        return Err(ParseError("not a DDP packet"));
+    }
+    let flags = bytes[0];
+    if flags & FLAG_QUERY != 0 || !matches!(bytes[3], ID_DISPLAY | ID_ALL) {
+        return Ok(None);
+    }
+    if !matches!(bytes[2], TYPE_UNDEFINED | TYPE_LEGACY_RGB | TYPE_RGB24) {
+        return Err(ParseError("unsupported DDP data type"));
+    }
+    let offset = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let len = u16::from_be_bytes([bytes[8], bytes[9]]) as usize;
+    let start = if flags & FLAG_TIMECODE != 0 {
+        HEADER_LEN + TIMECODE_LEN
+    } else {
+        HEADER_LEN
+    };
+    if start + len > bytes.len() {
+        return Err(ParseError("DDP length exceeds packet"));
+    }
+    Ok(Some(DataPacket {
+        push: flags & FLAG_PUSH != 0,
+        offset,
+        data: &bytes[start..start + len],
+    }))
+}
+
+/// A port receiving the pixel data of one output.
+struct Display {
+    socket: UdpSocket,
+    /// The display buffer, grown to the highest offset written so far.
+    buffer: Vec<u8>,
+}
+
+pub struct DdpSource {
+    displays: Vec<Display>,
+    packet: Vec<u8>,
+}
+
+impl DdpSource {
+    /// Listens on one port per output. The buffer of an output starts with
+    /// its number of LEDs if the strip length is known.
+    pub fn bind(outputs: &[(u16, Option<usize>)]) -> io::Result<Self> {
+        let mut displays = Vec::new();
+        for (port, num_leds) in outputs {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, *port))?;
+            socket.set_nonblocking(true)?;
+            displays.push(Display {
+                socket,
+                buffer: vec![0; num_leds.unwrap_or_default() * 3],
+            });
+        }
+        Ok(DdpSource {
+            displays,
+            packet: vec![0; MAX_PACKET_LEN],
+        })
+    }
+}
+
+impl Source for DdpSource {
+    fn poll(&mut self) -> Result<Option<Update>, Box<dyn Error>> {
+        for (output, display) in self.displays.iter_mut().enumerate() {
+            if let Some(leds) = display.poll(&mut self.packet)? {
+                return Ok(Some(Update::Frame(Received {
+                    output,
+                    sequence: None,
+                    present_at: None,
+                    rendered_at: None,
+                    pixels: Pixels::Rgb8(leds),
+                })));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Display {
+    /// Reads the packets which arrived until one pushes the buffer.
+    fn poll(&mut self, datagram: &mut [u8]) -> Result<Option<Vec<RGB8>>, Box<dyn Error>> {
+        loop {
+            let len = match self.socket.recv(datagram) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let Some(packet) = parse(&datagram[..len])? else {
+                continue;
+            };
+            let end = packet.offset + packet.data.len();
+            if end > MAX_BYTES {
+                return Err(ParseError("DDP offset beyond any strip").into());
+            }
+            if end > self.buffer.len() {
+                self.buffer.resize(end, 0);
+            }
+            self.buffer[packet.offset..end].copy_from_slice(packet.data);
+            if packet.push {
+                let leds = self
+                    .buffer
+                    .chunks_exact(3)
+                    .map(|rgb| RGB8::new(rgb[0], rgb[1], rgb[2]))
+                    .collect();
+                return Ok(Some(leds));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_data_packet() {
+        let bytes = [0x41, 3, TYPE_RGB24, ID_DISPLAY, 0, 0, 0, 6, 0, 3, 1, 2, 3];
+        assert_eq!(
+            parse(&bytes),
+            Ok(Some(DataPacket {
+                push: true,
+                offset: 6,
+                data: &[1, 2, 3],
+            }))
+        );
+        assert!(parse(&bytes[..12]).is_err());
+
+        let query = [0x42, 0, 0, 251, 0, 0, 0, 0, 0, 0];
+        assert_eq!(parse(&query), Ok(None));
+
+        let mut timecode = vec![0x50, 0, TYPE_RGB24, ID_DISPLAY, 0, 0, 0, 0, 0, 3];
+        timecode.extend_from_slice(&[0, 0, 0, 1, 4, 5, 6]);
+        assert_eq!(parse(&timecode).unwrap().unwrap().data, &[4, 5, 6]);
+    }
+
+    #[test]
+    fn accept_broadcasts_but_not_dmx() {
+        let mut bytes = [0x41, 0, TYPE_RGB24, ID_ALL, 0, 0, 0, 0, 0, 3, 1, 2, 3];
+        assert_eq!(parse(&bytes).unwrap().unwrap().data, &[1, 2, 3]);
+        // DMX transit
+        bytes[3] = 254;
+        assert_eq!(parse(&bytes), Ok(None));
+    }
+}
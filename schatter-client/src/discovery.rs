@@ -0,0 +1,33 @@
+//! Announces the client on the local network, so the server finds it without
+//! knowing its address.
+
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use schatter_lib::discovery::Announcement;
+use schatter_lib::protocol::DISCOVERY_PORT;
+
+/// Broadcasts the announcement every `interval` from a thread of its own.
+pub fn spawn(announcement: Announcement, interval: Duration) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+    let packet = announcement.encode();
+    thread::spawn(move || {
+        let mut failing = false;
+        loop {
+            match socket.send_to(&packet, (Ipv4Addr::BROADCAST, DISCOVERY_PORT)) {
+                Ok(_) => failing = false,
+                // Only report the first failure until the network is back.
+                Err(e) if !failing => {
+                    println!("Could not announce the client: {}", e);
+                    failing = true;
+                }
+                Err(_) => (),
+            }
+            thread::sleep(interval);
+        }
+    });
+    Ok(())
+}
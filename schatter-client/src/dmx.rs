@@ -0,0 +1,149 @@
+use smart_leds::colors::BLACK;
+use smart_leds::RGB8;
+
+/// LEDs in a universe, using 510 of its 512 channels.
+pub const LEDS_PER_UNIVERSE: usize = 170;
+const CHANNELS_PER_UNIVERSE: usize = 512;
+
+/// Maps consecutive DMX universes onto the strip, three channels per LED, and
+/// collects them into frames. Only the first universe is shifted by the
+/// channel offset, every following one starts at its first channel.
+pub struct Universes {
+    start: u16,
+    channel_offset: usize,
+    leds: Vec<RGB8>,
+    received: Vec<bool>,
+}
+
+impl Universes {
+    pub fn new(start: u16, channel_offset: usize, num_leds: usize) -> Self {
+        Universes {
+            start,
+            channel_offset,
+            leds: vec![BLACK; num_leds],
+            received: vec![false; universe_count(channel_offset, num_leds)],
+        }
+    }
+
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    /// The universes the strip is spread over.
+    pub fn universes(&self) -> impl Iterator<Item = u16> {
+        let start = self.start;
+        (0..self.received.len()).map(move |i| start.wrapping_add(i as u16))
+    }
+
+    pub fn contains(&self, universe: u16) -> bool {
+        (universe.wrapping_sub(self.start) as usize) < self.received.len()
+    }
+
+    /// Copies the channels of a universe onto its LEDs. Returns the frame once
+    /// every universe arrived, or early if a universe arrives a second time
+    /// because the sender skips unchanged ones.
+    pub fn push(&mut self, universe: u16, channels: &[u8]) -> Option<Vec<RGB8>> {
+        if !self.contains(universe) {
+            return None;
+        }
+        let index = universe.wrapping_sub(self.start) as usize;
+        let mut frame = None;
+        if self.received[index] {
+            frame = Some(self.leds.clone());
+            self.received.fill(false);
+        }
+
+        let (first_led, first_channel) = match index {
+            0 => (0, self.channel_offset),
+            _ => (
+                first_capacity(self.channel_offset) + (index - 1) * LEDS_PER_UNIVERSE,
+                0,
+            ),
+        };
+        let channels = channels.get(first_channel..).unwrap_or_default();
+        let first_led = first_led.min(self.leds.len());
+        for (led, rgb) in self.leds[first_led..]
+            .iter_mut()
+            .zip(channels.chunks_exact(3))
+            .take(LEDS_PER_UNIVERSE)
+        {
+            *led = RGB8::new(rgb[0], rgb[1], rgb[2]);
+        }
+
+        self.received[index] = true;
+        if self.received.iter().all(|received| *received) {
+            self.received.fill(false);
+            return Some(self.leds.clone());
+        }
+        frame
+    }
+}
+
+/// The strip whose universes include `universe`.
+pub fn find(outputs: &[Universes], universe: u16) -> Option<usize> {
+    outputs
+        .iter()
+        .position(|universes| universes.contains(universe))
+}
+
+/// No sequence number seen yet for every universe of every strip.
+pub fn sequences(outputs: &[Universes]) -> Vec<Vec<Option<u8>>> {
+    outputs
+        .iter()
+        .map(|universes| vec![None; universes.universes().count()])
+        .collect()
+}
+
+/// Number of universes a strip of `num_leds` spans.
+pub fn universe_count(channel_offset: usize, num_leds: usize) -> usize {
+    1 + num_leds
+        .saturating_sub(first_capacity(channel_offset))
+        .div_ceil(LEDS_PER_UNIVERSE)
+}
+
+fn first_capacity(channel_offset: usize) -> usize {
+    (CHANNELS_PER_UNIVERSE.saturating_sub(channel_offset) / 3).min(LEDS_PER_UNIVERSE)
+}
+
+/// Tracks the sequence number of a universe and tells whether a packet is
+/// older than the last one, as described in E1.31 6.7.2. Art-Net uses the
+/// same rule.
+pub fn is_stale(last: &mut Option<u8>, sequence: u8) -> bool {
+    if let Some(previous) = *last {
+        let diff = sequence.wrapping_sub(previous) as i8;
+        if diff <= 0 && diff > -20 {
+            return true;
+        }
+    }
+    *last = Some(sequence);
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spread_leds_over_universes() {
+        let mut universes = Universes::new(1, 3, 200);
+        assert_eq!(universes.universes().collect::<Vec<_>>(), vec![1, 2]);
+
+        let mut first = vec![0; 512];
+        first[3..6].copy_from_slice(&[1, 2, 3]);
+        assert_eq!(universes.push(1, &first), None);
+        assert_eq!(universes.push(3, &[9; 512]), None);
+        let frame = universes.push(2, &[7; 512]).unwrap();
+        assert_eq!(frame[0], RGB8::new(1, 2, 3));
+        assert_eq!(frame[168], RGB8::new(0, 0, 0));
+        assert_eq!(frame[169], RGB8::new(7, 7, 7));
+        assert_eq!(frame.len(), 200);
+    }
+
+    #[test]
+    fn emit_frame_when_universe_repeats() {
+        let mut universes = Universes::new(0, 0, 340);
+        assert_eq!(universes.push(0, &[1; 510]), None);
+        let frame = universes.push(0, &[2; 510]).unwrap();
+        assert_eq!(frame[0], RGB8::new(1, 1, 1));
+    }
+}
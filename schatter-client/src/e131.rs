@@ -0,0 +1,167 @@
+//! Receives DMX universes over sACN (ANSI E1.31), unicast or multicast.
+
+use std::error::Error;
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+
+use crate::dmx::{self, Universes};
+use crate::source::{ParseError, Pixels, Received, Source, Update};
+use crate::stats::Report;
+
+pub const PORT: u16 = 5568;
+
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const OPTION_PREVIEW_DATA: u8 = 0x80;
+const OPTION_STREAM_TERMINATED: u8 = 0x40;
+const DMX_START_CODE: u8 = 0x00;
+const HEADER_LEN: usize = 126;
+const MAX_PACKET_LEN: usize = HEADER_LEN + 512;
+
+/// The fields of an E1.31 data packet the client cares about.
+#[derive(Debug, PartialEq)]
+pub struct DataPacket<'a> {
+    pub universe: u16,
+    pub sequence: u8,
+    pub options: u8,
+    pub channels: &'a [u8],
+}
+
+/// Parses a data packet. Other valid E1.31 packets, like synchronisation and
+/// universe discovery, and data with a non-zero start code return `None`.
+pub fn parse(bytes: &[u8]) -> Result<Option<DataPacket<'_>>, ParseError> {
+    if bytes.len() < 22 || &bytes[4..16] != ACN_PACKET_IDENTIFIER {
+        return Err(ParseError("not an E1.31 packet"));
+    }
+    let root_vector = u32::from_be_bytes([bytes[18], bytes[19], bytes[20], bytes[21]]);
+    if root_vector != VECTOR_ROOT_E131_DATA {
+        return Ok(None);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(ParseError("E1.31 data packet too short"));
+    }
+    let framing_vector = u32::from_be_bytes([bytes[40], bytes[41], bytes[42], bytes[43]]);
+    if framing_vector != VECTOR_E131_DATA_PACKET || bytes[117] != VECTOR_DMP_SET_PROPERTY {
+        return Err(ParseError("unexpected E1.31 vector"));
+    }
+    let property_count = u16::from_be_bytes([bytes[123], bytes[124]]) as usize;
+    if property_count == 0 || HEADER_LEN - 1 + property_count > bytes.len() {
+        return Err(ParseError("E1.31 property count exceeds packet"));
+    }
+    if bytes[125] != DMX_START_CODE {
+        return Ok(None);
+    }
+    Ok(Some(DataPacket {
+        universe: u16::from_be_bytes([bytes[113], bytes[114]]),
+        sequence: bytes[111],
+        options: bytes[112],
+        channels: &bytes[HEADER_LEN..HEADER_LEN - 1 + property_count],
+    }))
+}
+
+pub struct E131Source {
+    socket: UdpSocket,
+    outputs: Vec<Universes>,
+    sequences: Vec<Vec<Option<u8>>>,
+    buffer: Vec<u8>,
+    stale: Vec<u64>,
+}
+
+impl E131Source {
+    /// Listens for unicast packets and joins the multicast groups of all
+    /// universes of the strips.
+    pub fn bind(port: u16, outputs: Vec<Universes>) -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        socket.set_nonblocking(true)?;
+        for universe in outputs.iter().flat_map(Universes::universes) {
+            let [hi, lo] = universe.to_be_bytes();
+            socket.join_multicast_v4(&Ipv4Addr::new(239, 255, hi, lo), &Ipv4Addr::UNSPECIFIED)?;
+        }
+        Ok(E131Source {
+            socket,
+            sequences: dmx::sequences(&outputs),
+            stale: vec![0; outputs.len()],
+            outputs,
+            buffer: vec![0; MAX_PACKET_LEN],
+        })
+    }
+}
+
+impl Source for E131Source {
+    fn poll(&mut self) -> Result<Option<Update>, Box<dyn Error>> {
+        loop {
+            let len = match self.socket.recv(&mut self.buffer) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let Some(packet) = parse(&self.buffer[..len])? else {
+                continue;
+            };
+            if packet.options & (OPTION_PREVIEW_DATA | OPTION_STREAM_TERMINATED) != 0 {
+                continue;
+            }
+            let Some(output) = dmx::find(&self.outputs, packet.universe) else {
+                continue;
+            };
+            let universes = &mut self.outputs[output];
+            let index = packet.universe.wrapping_sub(universes.start()) as usize;
+            if dmx::is_stale(&mut self.sequences[output][index], packet.sequence) {
+                self.stale[output] += 1;
+                continue;
+            }
+            if let Some(leds) = universes.push(packet.universe, packet.channels) {
+                return Ok(Some(Update::Frame(Received {
+                    output,
+                    sequence: None,
+                    present_at: None,
+                    rendered_at: None,
+                    pixels: Pixels::Rgb8(leds),
+                })));
+            }
+        }
+    }
+
+    fn extend_report(&self, output: usize, report: &mut Report) {
+        report.reordered = self.stale[output];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_packet(universe: u16, channels: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_LEN];
+        bytes[0..2].copy_from_slice(&0x0010u16.to_be_bytes());
+        bytes[4..16].copy_from_slice(ACN_PACKET_IDENTIFIER);
+        bytes[18..22].copy_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+        bytes[40..44].copy_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+        bytes[111] = 7;
+        bytes[113..115].copy_from_slice(&universe.to_be_bytes());
+        bytes[117] = VECTOR_DMP_SET_PROPERTY;
+        bytes[118] = 0xa1;
+        bytes[122] = 1;
+        bytes[123..125].copy_from_slice(&(channels.len() as u16 + 1).to_be_bytes());
+        bytes.extend_from_slice(channels);
+        bytes
+    }
+
+    #[test]
+    fn parse_data_packet() {
+        let bytes = data_packet(3, &[1, 2, 3]);
+        assert_eq!(
+            parse(&bytes),
+            Ok(Some(DataPacket {
+                universe: 3,
+                sequence: 7,
+                options: 0,
+                channels: &[1, 2, 3],
+            }))
+        );
+        assert!(parse(&bytes[..100]).is_err());
+        assert!(parse(b"Art-Net\0").is_err());
+    }
+}
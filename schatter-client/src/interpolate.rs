@@ -0,0 +1,137 @@
+//! Plays the received frames back at a fixed rate, a fixed latency after they
+//! arrived, so the uneven rate the server sends them at does not show as
+//! judder on the strip.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::config::Interpolation;
+
+pub struct Interpolator {
+    mode: Interpolation,
+    latency: Duration,
+    /// The received frames with their arrival time, oldest first. The first
+    /// one is the frame being shown.
+    frames: VecDeque<(Instant, Vec<[u16; 3]>)>,
+    late: u64,
+}
+
+impl Interpolator {
+    pub fn new(mode: Interpolation, latency: Duration) -> Self {
+        Interpolator {
+            mode,
+            latency,
+            frames: VecDeque::new(),
+            late: 0,
+        }
+    }
+
+    pub fn push(&mut self, pixels: Vec<[u16; 3]>) {
+        self.push_at(Instant::now(), pixels);
+    }
+
+    fn push_at(&mut self, arrival: Instant, pixels: Vec<[u16; 3]>) {
+        // Playback already reached the newest frame, so it was held for
+        // longer than it should have been.
+        if let Some((newest, _)) = self.frames.back() {
+            if *newest + self.latency <= arrival {
+                self.late += 1;
+            }
+        }
+        self.frames.push_back((arrival, pixels));
+    }
+
+    /// The frame to show at `now`, or `None` while the first frame is not
+    /// due yet.
+    pub fn frame(&mut self, now: Instant) -> Option<Vec<[u16; 3]>> {
+        let playback = now.checked_sub(self.latency)?;
+        while self.frames.len() > 1 && self.frames[1].0 <= playback {
+            self.frames.pop_front();
+        }
+        let (start, current) = self.frames.front()?;
+        if *start > playback {
+            return None;
+        }
+        let Some((end, next)) = self.frames.get(1) else {
+            return Some(current.clone());
+        };
+        if self.mode == Interpolation::Hold || current.len() != next.len() {
+            return Some(current.clone());
+        }
+        let fraction = (playback - *start).as_secs_f32() / (*end - *start).as_secs_f32();
+        Some(blend(current, next, fraction))
+    }
+
+    /// Number of frames which arrived after playback ran out of frames.
+    pub fn late_frames(&self) -> u64 {
+        self.late
+    }
+
+    /// Forgets the received frames, so a stream starting again is not
+    /// blended with the frames of the previous one.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+fn blend(from: &[[u16; 3]], to: &[[u16; 3]], fraction: f32) -> Vec<[u16; 3]> {
+    let weight = (fraction.clamp(0.0, 1.0) * 65536.0) as u32;
+    from.iter()
+        .zip(to)
+        .map(|(from, to)| {
+            let mut pixel = [0; 3];
+            for ((channel, from), to) in pixel.iter_mut().zip(from).zip(to) {
+                let value = *from as u32 * (65536 - weight) + *to as u32 * weight;
+                *channel = (value >> 16) as u16;
+            }
+            pixel
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATENCY: Duration = Duration::from_millis(50);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn blend_between_frames() {
+        let start = Instant::now();
+        let mut interpolator = Interpolator::new(Interpolation::Linear, LATENCY);
+        interpolator.push_at(start, vec![[0, 1000, 65535]]);
+        interpolator.push_at(start + ms(20), vec![[1000, 1000, 0]]);
+
+        assert_eq!(interpolator.frame(start + ms(40)), None);
+        assert_eq!(
+            interpolator.frame(start + ms(50)),
+            Some(vec![[0, 1000, 65535]])
+        );
+        let [halfway] = interpolator.frame(start + ms(60)).unwrap()[..] else {
+            panic!("expected one pixel");
+        };
+        assert!((499..=500).contains(&halfway[0]), "{:?}", halfway);
+        assert!((32767..=32768).contains(&halfway[2]), "{:?}", halfway);
+        assert_eq!(
+            interpolator.frame(start + ms(90)),
+            Some(vec![[1000, 1000, 0]])
+        );
+        assert_eq!(interpolator.late_frames(), 0);
+    }
+
+    #[test]
+    fn hold_frames_and_count_late_ones() {
+        let start = Instant::now();
+        let mut interpolator = Interpolator::new(Interpolation::Hold, LATENCY);
+        interpolator.push_at(start, vec![[0; 3]]);
+        interpolator.push_at(start + ms(20), vec![[1000; 3]]);
+        assert_eq!(interpolator.frame(start + ms(69)), Some(vec![[0; 3]]));
+
+        interpolator.push_at(start + ms(100), vec![[2000; 3]]);
+        assert_eq!(interpolator.late_frames(), 1);
+    }
+}
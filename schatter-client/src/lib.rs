@@ -1,14 +1,33 @@
+pub mod artnet;
+pub mod clock;
+pub mod config;
+pub mod ddp;
+pub mod discovery;
+pub mod dmx;
+pub mod e131;
+pub mod interpolate;
+pub mod opc;
+pub mod osc;
+pub mod pattern;
+pub mod remap;
+pub mod replay;
+pub mod sink;
+pub mod source;
+pub mod stats;
+pub mod status;
+pub mod strip;
+pub mod watchdog;
+
 use std::fmt;
 use std::io::{Write, stdout};
 
 use colored::*;
-use nannou_osc as osc;
 use smart_leds::RGB8;
 use termion::cursor::Goto;
 
 struct RGB8Wrapper(RGB8);
 
-pub fn osc_color_to_rgb8(color: osc::Color) -> RGB8 {
+pub fn osc_color_to_rgb8(color: nannou_osc::Color) -> RGB8 {
     RGB8 {
         r: color.red,
         g: color.green,
@@ -21,7 +40,7 @@ impl fmt::Display for RGB8Wrapper {
     }
 }
 
-pub fn display(leds: &Vec<RGB8>) {
+pub fn display(leds: &[RGB8]) {
     print!("{}", Goto(1, 1)); // Move the cursor to the beginning of the line
     for led in leds {
         print!("{}", RGB8Wrapper(*led));
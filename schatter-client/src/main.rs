@@ -1,114 +1,555 @@
-use core::time;
-use std::{env, thread};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{process, thread};
 
-use nannou_osc as osc;
-use osc::Packet;
-use schatter_client::{display, osc_color_to_rgb8};
-use smart_leds::colors::*;
-use smart_leds::{SmartLedsWrite, RGB8};
-#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-use ws281x_rpi::Ws2812Rpi;
+use clap::Parser;
+use schatter_client::artnet::{self, ArtNetSource};
+use schatter_client::clock::Clock;
+use schatter_client::config::{Cli, Command, Config, Interpolation, TimeoutAction};
+use schatter_client::ddp::{self, DdpSource};
+use schatter_client::discovery;
+use schatter_client::dmx::Universes;
+use schatter_client::e131::{self, E131Source};
+use schatter_client::interpolate::Interpolator;
+use schatter_client::opc::{self, OpcSource};
+use schatter_client::osc::{self, OscSource};
+use schatter_client::pattern::Animation;
+use schatter_client::replay::ReplaySource;
+use schatter_client::source::{Pixels, Received, Source, Update};
+use schatter_client::stats::Stats;
+use schatter_client::status::{self, Heartbeat};
+use schatter_client::strip::Strip;
+use schatter_client::watchdog::{self, idle_frame, Watchdog};
+use schatter_lib::auth::Key;
+use schatter_lib::discovery::Announcement;
 
-const NUM_LEDS: i32 = 700;
-const MTU: usize = 10000;
+const NUM_LEDS: usize = 700;
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+const REFRESH_INTERVAL: Duration = Duration::from_millis(20);
+const PATTERN_INTERVAL: Duration = Duration::from_millis(5);
+/// How long a frame waits for the frames of the other outputs, so the strips
+/// showing two halves of a face are written together.
+const SYNC_WINDOW: Duration = Duration::from_millis(4);
+/// Frames stamped further ahead than this are shown right away, as the clock
+/// is probably off.
+const MAX_PRESENTATION_DELAY: Duration = Duration::from_secs(1);
+/// Authenticated frames rendered longer ago than this are captured traffic
+/// sent again, not late ones.
+const MAX_FRAME_AGE: Duration = Duration::from_secs(2);
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let usage = format!(
-        "usage: {} {{ test,stream }} {{ port }} {{ pin }} {{ dma }}",
-        &args[0]
-    );
-
-    let port: u16 = match args.get(2) {
-        Some(p) => p.parse().expect("Invalid port number"),
-        None => 12345,
-    };
-    let pin: i32 = match args.get(3) {
-        Some(p) => p.parse().expect("Invalid pin"),
-        None => 18,
-    };
-    let dma: i32 = match args.get(4) {
-        Some(p) => p.parse().expect("Invalid dma"),
-        None => 10,
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
     };
+    let running = watchdog::running();
+    let outputs = config.outputs();
 
-    match args[1].as_str() {
-        "test" => {
-            test(pin, dma);
-        }
-        "stream" => {
-            stream(port, pin, dma);
-        }
-        _ => {
-            println!("{}", usage);
-            ::std::process::exit(1);
-        }
-    }
-}
-
-fn stream(port: u16, pin: i32, dma: i32) {
-    let receiver = osc::Receiver::bind_with_mtu(port, MTU).expect("Could not bind to socket");
-    let (packet, _) = receiver.recv().unwrap();
-    let len = get_rgb(packet).len();
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    let mut ws = Ws2812Rpi::new(len.try_into().unwrap(), pin, dma).unwrap();
-    loop {
-        for (packet, _) in receiver.iter() {
-            // const DELAY: time::Duration = time::Duration::from_millis(30);
-            // thread::sleep(DELAY);
-            let stripe = get_rgb(packet);
-            #[cfg(debug_assertions)]
-            display(&stripe);
-            #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-            ws.write(stripe.iter().cloned()).unwrap();
-        }
-    }
-}
-
-fn test(pin: i32, dma: i32) {
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    let mut ws = Ws2812Rpi::new(NUM_LEDS, pin, dma).unwrap();
-    let pattern: Vec<RGB8> = vec![
-        WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE,
-        WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE,
-        WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE,
-        WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE,
-        WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE,
-        WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, WHITE,
-        WHITE, WHITE, WHITE, WHITE, WHITE, WHITE, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK,
-        BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK,
-        BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK,
-        BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK,
-        BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK,
-        BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK, BLACK,
-    ];
-    let mut stripe = Vec::default();
-    for i in 0..(NUM_LEDS) {
-        let n = (i as usize) % pattern.len();
-        stripe.push(pattern[n]);
-    }
-    loop {
-        const DELAY: time::Duration = time::Duration::from_millis(5);
-        thread::sleep(DELAY);
-        #[cfg(debug_assertions)]
-        display(&stripe);
-        stripe.rotate_right(1);
-        #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-        match ws.write(stripe.iter().cloned()) {
-            Ok(_) => (),
-            Err(e) => println!("{}", e),
-        }
-    }
-}
-
-fn get_rgb(packet: Packet) -> Vec<RGB8> {
-    packet
-        .into_msgs()
-        .into_iter()
-        .flat_map(|message| message.args.unwrap_or_default())
-        .filter_map(|arg| match arg {
-            osc::Type::Color(color) => Some(osc_color_to_rgb8(color)),
-            _ => None,
+    match cli.command {
+        Command::Test => test(&outputs, &running),
+        Command::Stream => {
+            let ports = own_ports(&config, osc::PORT);
+            let key = config
+                .secret
+                .as_deref()
+                .map(|secret| Key::new(secret.as_bytes()));
+            let mut source = bind(OscSource::bind(&ports, key), &ports);
+            announce(&config, ports);
+            stream(&mut source, outputs, &config, &running);
+        }
+        Command::E131 => {
+            let port = outputs[0].port.unwrap_or(e131::PORT);
+            let mut source = bind(E131Source::bind(port, universes(&outputs)), &[port]);
+            stream(&mut source, outputs, &config, &running);
+        }
+        Command::ArtNet => {
+            let port = outputs[0].port.unwrap_or(artnet::PORT);
+            let mut source = bind(ArtNetSource::bind(port, universes(&outputs)), &[port]);
+            stream(&mut source, outputs, &config, &running);
+        }
+        Command::Ddp => {
+            let ports = own_ports(&config, ddp::PORT);
+            let displays: Vec<_> = ports
+                .iter()
+                .zip(&outputs)
+                .map(|(port, output)| (*port, output.num_leds))
+                .collect();
+            let mut source = bind(DdpSource::bind(&displays), &ports);
+            stream(&mut source, outputs, &config, &running);
+        }
+        Command::Opc => {
+            let port = outputs[0].port.unwrap_or(opc::PORT);
+            let channels = outputs.iter().map(|output| output.opc_channel).collect();
+            let mut source = bind(OpcSource::bind(port, channels), &[port]);
+            stream(&mut source, outputs, &config, &running);
+        }
+        Command::Replay => {
+            let Some(path) = &config.recording else {
+                eprintln!("invalid configuration: recording is required for replay");
+                process::exit(2);
+            };
+            let id = client_id(&config);
+            let streams = (1..=outputs.len())
+                .map(|output| format!("{}:{}", id, output))
+                .collect();
+            let mut source = match ReplaySource::open(path, streams) {
+                Ok(source) => source,
+                Err(e) => {
+                    eprintln!("Could not replay {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            };
+            stream(&mut source, outputs, &config, &running);
+        }
+    }
+}
+
+/// Ports for protocols which receive every output on a port of its own,
+/// counting up from the default one.
+fn own_ports(config: &Config, default: u16) -> Vec<u16> {
+    match config.own_ports(default) {
+        Ok(ports) => ports,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
+
+fn bind<S, E: Into<Box<dyn Error>>>(source: Result<S, E>, ports: &[u16]) -> S {
+    match source {
+        Ok(source) => source,
+        Err(e) => {
+            let ports: Vec<String> = ports.iter().map(u16::to_string).collect();
+            eprintln!("Could not bind to port {}: {}", ports.join(", "), e.into());
+            process::exit(1);
+        }
+    }
+}
+
+/// DMX based protocols can not announce the length of the strips.
+fn universes(outputs: &[Config]) -> Vec<Universes> {
+    outputs
+        .iter()
+        .map(|output| match output.num_leds {
+            Some(num_leds) => Universes::new(output.universe, output.channel_offset, num_leds),
+            None => {
+                eprintln!("invalid configuration: num_leds is required for this protocol");
+                process::exit(2);
+            }
         })
         .collect()
 }
+
+fn open_strip(config: &Config, num_leds: usize) -> Strip {
+    match config.sink.open(num_leds, config.pin, config.dma) {
+        Ok(sink) => Strip::new(sink, num_leds, config),
+        Err(e) => {
+            eprintln!("Could not open sink {}: {}", config.sink, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// A strip and the state of the stream it shows.
+struct Output {
+    config: Config,
+    strip: Option<Strip>,
+    watchdog: Watchdog,
+    stats: Stats,
+    level: u8,
+    /// The newest frame which was not written yet.
+    pending: Option<Received>,
+    /// Replaces writing frames as they arrive if interpolation is on.
+    interpolator: Option<Interpolator>,
+    /// Frames waiting for their presentation time, oldest first.
+    scheduled: VecDeque<(Instant, Received)>,
+    /// Shown until a stream arrives and after it timed out.
+    fallback: Option<Animation>,
+}
+
+impl Output {
+    fn new(config: Config) -> Self {
+        Output {
+            strip: config
+                .num_leds
+                .map(|num_leds| open_strip(&config, num_leds)),
+            watchdog: Watchdog::new(
+                Duration::from_millis(config.timeout_ms),
+                Duration::from_millis(config.fade_ms),
+            ),
+            stats: Stats::new(),
+            level: u8::MAX,
+            pending: None,
+            interpolator: match config.interpolation {
+                Interpolation::Off => None,
+                mode => Some(Interpolator::new(
+                    mode,
+                    Duration::from_millis(config.latency_ms),
+                )),
+            },
+            scheduled: VecDeque::new(),
+            fallback: (config.timeout_action == TimeoutAction::Fallback)
+                .then(|| Animation::fallback(&config)),
+            config,
+        }
+    }
+
+    /// Hands a frame due now to the interpolator, or else keeps it to be
+    /// written with the frames of the other outputs.
+    fn receive(
+        &mut self,
+        received: Received,
+        first_pending: &mut Option<Instant>,
+        clock: Option<&Clock>,
+    ) {
+        match self.interpolator.as_mut() {
+            Some(interpolator) => {
+                // Interpolated frames are shown the configured latency later.
+                if let Some(latency) = latency(&received, clock) {
+                    let buffered = Duration::from_millis(self.config.latency_ms);
+                    self.stats.record_latency(latency + buffered);
+                }
+                interpolator.push(received.pixels.into_rgb16());
+            }
+            None => {
+                self.pending = Some(received);
+                first_pending.get_or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    /// The oldest scheduled frame, once its presentation time has come.
+    fn take_due(&mut self, now: Instant) -> Option<Received> {
+        if self.scheduled.front()?.0 > now {
+            return None;
+        }
+        self.scheduled.pop_front().map(|(_, received)| received)
+    }
+
+    /// Whether a frame of this output is due, as it is still streaming but
+    /// its next frame did not arrive yet.
+    fn awaited(&self) -> bool {
+        self.pending.is_none() && !self.watchdog.is_silent()
+    }
+
+    fn write_pending(&mut self, clock: Option<&Clock>) -> Result<(), Box<dyn Error>> {
+        let Some(received) = self.pending.take() else {
+            return Ok(());
+        };
+        let config = &self.config;
+        let pixels = &received.pixels;
+        let strip = self
+            .strip
+            .get_or_insert_with(|| open_strip(config, config.remap.strip_len(pixels.len())));
+        self.level = u8::MAX;
+        match pixels {
+            Pixels::Rgb8(leds) => strip.show(leds)?,
+            Pixels::Rgb16(pixels) => strip.show_rgb16(pixels)?,
+        }
+        if let Some(latency) = latency(&received, clock) {
+            self.stats.record_latency(latency);
+        }
+        Ok(())
+    }
+
+    /// Fades the last frame out once the stream went quiet for longer than
+    /// the configured timeout, then blanks the strip or shows the idle or
+    /// fallback pattern. The fallback pattern also runs until the first
+    /// frame arrived.
+    fn show_timeout(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(strip) = self.strip.as_mut() else {
+            return Ok(());
+        };
+        let watchdog = &self.watchdog;
+        let previous = self.level;
+        self.level = watchdog.level();
+        let streamed = self.stats.received() > 0;
+        match &self.fallback {
+            Some(fallback) if watchdog.expired() || !streamed => {
+                strip.show(&fallback.frame(strip.frame_len()))
+            }
+            _ if watchdog.expired() && self.config.timeout_action == TimeoutAction::Idle => {
+                strip.show(&idle_frame(strip.frame_len(), watchdog.expired_for()))
+            }
+            _ if self.level != previous => strip.fade(self.level),
+            _ => strip.refresh(REFRESH_INTERVAL),
+        }
+    }
+
+    /// Writes the interpolated frame due at `now`. Once the stream went
+    /// quiet the watchdog takes over and the received frames are dropped.
+    fn render(&mut self, now: Instant) -> Result<(), Box<dyn Error>> {
+        let Some(interpolator) = self.interpolator.as_mut() else {
+            return Ok(());
+        };
+        if self.watchdog.is_silent() {
+            interpolator.clear();
+            return Ok(());
+        }
+        let Some(frame) = interpolator.frame(now) else {
+            return Ok(());
+        };
+        let config = &self.config;
+        let strip = self
+            .strip
+            .get_or_insert_with(|| open_strip(config, config.remap.strip_len(frame.len())));
+        self.level = u8::MAX;
+        strip.show_rgb16(&frame)
+    }
+}
+
+/// The time since the server rendered a frame, if it stamped the frame and
+/// the clock is synchronised with the server's.
+fn latency(received: &Received, clock: Option<&Clock>) -> Option<Duration> {
+    clock?.elapsed_since(received.rendered_at?)
+}
+
+/// Whether an authenticated frame is too old to be anything but a replay,
+/// which a tag alone does not reveal. Only known once the clock is
+/// synchronised, and frames without a render time count as expired then.
+fn expired(received: &Received, clock: Option<&Clock>) -> bool {
+    clock.is_some_and(Clock::is_synchronised)
+        && latency(received, clock).is_none_or(|age| age > MAX_FRAME_AGE)
+}
+
+/// The configured client id, or else the host name.
+fn client_id(config: &Config) -> String {
+    config
+        .client_id
+        .clone()
+        .or_else(status::hostname)
+        .unwrap_or_else(|| "schatter-client".to_string())
+}
+
+/// Lets the server find the client and send to the ports of its outputs.
+fn announce(config: &Config, ports: Vec<u16>) {
+    if config.announce_interval_ms == 0 {
+        return;
+    }
+    let id = client_id(config);
+    println!("Announcing the client as {}", id);
+    let announcement = Announcement { id, ports };
+    let interval = Duration::from_millis(config.announce_interval_ms);
+    if let Err(e) = discovery::spawn(announcement, interval) {
+        println!("Could not announce the client: {}", e);
+    }
+}
+
+fn connect_heartbeat(config: &Config) -> Option<Heartbeat> {
+    let server = config.server.as_deref()?;
+    let id = client_id(config);
+    let interval = Duration::from_millis(config.status_interval_ms);
+    match Heartbeat::connect(server, id, interval) {
+        Ok(heartbeat) => Some(heartbeat),
+        Err(e) => {
+            eprintln!("Could not connect to server {}: {}", server, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The address of the server to synchronise the clock with: the configured
+/// one, or else the one the frames come from.
+fn server_ip(config: &Config, source: &dyn Source) -> Option<IpAddr> {
+    let configured = config.server.as_deref().and_then(|server| {
+        let mut addresses = server.to_socket_addrs().ok()?;
+        addresses.next().map(|address| address.ip())
+    });
+    configured.or_else(|| source.sender())
+}
+
+fn clock(config: &Config) -> Option<Clock> {
+    if config.sync_interval_ms == 0 {
+        return None;
+    }
+    let key = config
+        .secret
+        .as_deref()
+        .map(|secret| Key::new(secret.as_bytes()));
+    match Clock::new(Duration::from_millis(config.sync_interval_ms), key) {
+        Ok(clock) => Some(clock),
+        Err(e) => {
+            println!(
+                "Could not synchronise the clock, showing frames as they arrive: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Prints an error and keeps it for the next status message.
+fn log_error(last_error: &mut Option<String>, error: impl fmt::Display) {
+    println!("{}", error);
+    *last_error = Some(error.to_string());
+}
+
+fn stream(source: &mut dyn Source, outputs: Vec<Config>, config: &Config, running: &AtomicBool) {
+    let mut outputs: Vec<Output> = outputs.into_iter().map(Output::new).collect();
+    let stats_interval = Duration::from_millis(config.stats_interval_ms);
+    let mut last_stats = Instant::now();
+    let mut last_refresh = Instant::now();
+    let mut first_pending: Option<Instant> = None;
+    let render_interval = Duration::from_secs_f64(1.0 / config.refresh_hz as f64);
+    let mut next_render = Instant::now();
+    let mut heartbeat = connect_heartbeat(config);
+    let mut last_error: Option<String> = None;
+    let mut clock = clock(config);
+    while running.load(Ordering::SeqCst) {
+        match source.poll() {
+            Ok(Some(Update::Resize { output, num_leds })) => resize(&mut outputs[output], num_leds),
+            Ok(Some(Update::Frame(received)))
+                if source.authenticated() && expired(&received, clock.as_ref()) =>
+            {
+                outputs[received.output].stats.record_expired();
+            }
+            Ok(Some(Update::Frame(received))) => {
+                let output = &mut outputs[received.output];
+                output.stats.record(received.sequence);
+                output.watchdog.feed();
+                let due = received
+                    .present_at
+                    .zip(clock.as_ref())
+                    .and_then(|(time, clock)| clock.local_time(time))
+                    .filter(|due| {
+                        due.saturating_duration_since(Instant::now()) <= MAX_PRESENTATION_DELAY
+                    });
+                match due {
+                    Some(due) => output.scheduled.push_back((due, received)),
+                    None => output.receive(received, &mut first_pending, clock.as_ref()),
+                }
+            }
+            Ok(None) => {
+                if !stats_interval.is_zero() && last_stats.elapsed() >= stats_interval {
+                    last_stats = Instant::now();
+                    for (index, output) in outputs.iter_mut().enumerate() {
+                        let mut report = output.stats.report();
+                        source.extend_report(index, &mut report);
+                        report.limited = output.strip.as_ref().map_or(0, Strip::limited_frames);
+                        report.late = output
+                            .interpolator
+                            .as_ref()
+                            .map_or(0, Interpolator::late_frames);
+                        match config.outputs.len() {
+                            0 => println!("{}", report),
+                            _ => println!("Output {}: {}", index + 1, report),
+                        }
+                    }
+                }
+                if let Some(clock) = clock.as_mut() {
+                    if let Some(server) = server_ip(config, source) {
+                        clock.poll(server);
+                    }
+                }
+                if let Some(heartbeat) = heartbeat.as_mut().filter(|h| h.is_due()) {
+                    let stats = outputs.iter().map(|output| &output.stats);
+                    if let Err(e) = heartbeat.send(stats, last_error.take().as_deref()) {
+                        println!("Could not send status: {}", e);
+                    }
+                }
+                if last_refresh.elapsed() >= REFRESH_INTERVAL {
+                    last_refresh = Instant::now();
+                    for output in outputs.iter_mut() {
+                        if let Err(e) = output.show_timeout() {
+                            log_error(&mut last_error, e);
+                        }
+                    }
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(e) => log_error(&mut last_error, format!("Dropping packet: {}", e)),
+        }
+        let now = Instant::now();
+        for output in outputs.iter_mut() {
+            while let Some(received) = output.take_due(now) {
+                output.receive(received, &mut first_pending, clock.as_ref());
+            }
+        }
+        // Write the frames of all outputs at once as soon as every streaming
+        // output has one, or the first one waited long enough.
+        if let Some(since) = first_pending {
+            if since.elapsed() >= SYNC_WINDOW || !outputs.iter().any(Output::awaited) {
+                first_pending = None;
+                for output in outputs.iter_mut() {
+                    if let Err(e) = output.write_pending(clock.as_ref()) {
+                        log_error(&mut last_error, e);
+                    }
+                }
+            }
+        }
+        if config.interpolation != Interpolation::Off && Instant::now() >= next_render {
+            let now = Instant::now();
+            next_render += render_interval;
+            if next_render < now {
+                next_render = now + render_interval;
+            }
+            for output in outputs.iter_mut() {
+                if let Err(e) = output.render(now) {
+                    log_error(&mut last_error, e);
+                }
+            }
+        }
+    }
+    for output in outputs.iter_mut() {
+        if let Some(strip) = output.strip.as_mut() {
+            strip.blank().ok();
+        }
+    }
+}
+
+/// Reopens the strip with the length the server announced. The old sink is
+/// dropped first, as the ws281x driver can not open the same channel twice.
+fn resize(output: &mut Output, frame_len: usize) {
+    let num_leds = output.config.remap.strip_len(frame_len);
+    if output.strip.as_ref().map(Strip::num_leds) == Some(num_leds) {
+        return;
+    }
+    println!(
+        "Server announced {} LEDs on pin {}, reopening the strip",
+        frame_len, output.config.pin
+    );
+    if let Some(mut strip) = output.strip.take() {
+        strip.blank().ok();
+    }
+    output.strip = Some(open_strip(&output.config, num_leds));
+}
+
+fn test(outputs: &[Config], running: &AtomicBool) {
+    let mut strips: Vec<Strip> = outputs
+        .iter()
+        .map(|config| open_strip(config, config.num_leds.unwrap_or(NUM_LEDS)))
+        .collect();
+    let animations: Vec<Animation> = outputs.iter().map(Animation::new).collect();
+    let mut captions: Vec<Option<String>> = vec![None; strips.len()];
+    while running.load(Ordering::SeqCst) {
+        thread::sleep(PATTERN_INTERVAL);
+        for (index, (strip, animation)) in strips.iter_mut().zip(&animations).enumerate() {
+            // Print what the strip shows whenever it changes.
+            let caption = animation.caption(strip.frame_len());
+            if caption != captions[index] {
+                if let Some(caption) = &caption {
+                    match outputs.len() {
+                        1 => println!("{}", caption),
+                        _ => println!("Output {}: {}", index + 1, caption),
+                    }
+                }
+                captions[index] = caption;
+            }
+            if let Err(e) = strip.show(&animation.frame(strip.frame_len())) {
+                println!("{}", e);
+            }
+        }
+    }
+    for strip in strips.iter_mut() {
+        strip.blank().ok();
+    }
+}
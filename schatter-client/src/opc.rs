@@ -0,0 +1,207 @@
+//! An Open Pixel Control server. Clients connect over TCP and send "set pixel
+//! colors" messages for the channel of one of the strips, or for all of them.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use smart_leds::RGB8;
+
+use crate::source::{Pixels, Received, Source, Update};
+
+pub const PORT: u16 = 7890;
+
+const HEADER_LEN: usize = 4;
+const BROADCAST_CHANNEL: u8 = 0;
+const SET_PIXEL_COLORS: u8 = 0;
+const READ_CHUNK: usize = 4096;
+/// The largest buffer a sender can make the client allocate, the length of
+/// the longest message. Once it is full it holds at least one complete
+/// message, so reading the rest can wait until that was parsed.
+const MAX_BUFFER_LEN: usize = HEADER_LEN + u16::MAX as usize;
+
+#[derive(Debug, PartialEq)]
+pub struct Message<'a> {
+    pub channel: u8,
+    pub command: u8,
+    pub data: &'a [u8],
+}
+
+/// Parses the first message of `bytes` and returns it together with its
+/// length, or `None` if it did not arrive completely yet.
+pub fn parse(bytes: &[u8]) -> Option<(Message<'_>, usize)> {
+    if bytes.len() < HEADER_LEN {
+        return None;
+    }
+    let len = HEADER_LEN + u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+    let data = bytes.get(HEADER_LEN..len)?;
+    let message = Message {
+        channel: bytes[0],
+        command: bytes[1],
+        data,
+    };
+    Some((message, len))
+}
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+pub struct OpcSource {
+    listener: TcpListener,
+    /// The channel of every output.
+    channels: Vec<u8>,
+    connections: Vec<Connection>,
+    pending: VecDeque<Update>,
+}
+
+impl OpcSource {
+    /// Listens for OPC clients and shows the messages sent to `channels` on
+    /// the output of the same index.
+    pub fn bind(port: u16, channels: Vec<u8>) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(OpcSource {
+            listener,
+            channels,
+            connections: Vec::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    println!("OPC client {} connected", address);
+                    self.connections.push(Connection {
+                        stream,
+                        buffer: Vec::new(),
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads what arrived on every connection and queues the complete
+    /// messages, dropping connections which were closed.
+    fn read(&mut self) {
+        let channels = &self.channels;
+        let pending = &mut self.pending;
+        self.connections.retain_mut(|connection| {
+            let open = connection.fill();
+            let mut consumed = 0;
+            while let Some((message, len)) = parse(&connection.buffer[consumed..]) {
+                consumed += len;
+                if message.command != SET_PIXEL_COLORS {
+                    continue;
+                }
+                let leds: Vec<RGB8> = message
+                    .data
+                    .chunks_exact(3)
+                    .map(|rgb| RGB8::new(rgb[0], rgb[1], rgb[2]))
+                    .collect();
+                for (output, channel) in channels.iter().enumerate() {
+                    if message.channel == *channel || message.channel == BROADCAST_CHANNEL {
+                        pending.push_back(Update::Frame(Received {
+                            output,
+                            sequence: None,
+                            present_at: None,
+                            rendered_at: None,
+                            pixels: Pixels::Rgb8(leds.clone()),
+                        }));
+                    }
+                }
+            }
+            connection.buffer.drain(..consumed);
+            open
+        });
+    }
+}
+
+impl Connection {
+    /// Reads until the socket would block or the buffer is full. Returns
+    /// false once the peer closed the connection or it failed.
+    fn fill(&mut self) -> bool {
+        let mut chunk = [0; READ_CHUNK];
+        loop {
+            let space = MAX_BUFFER_LEN - self.buffer.len();
+            if space == 0 {
+                return true;
+            }
+            match self.stream.read(&mut chunk[..space.min(READ_CHUNK)]) {
+                Ok(0) => return false,
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    println!("Closing OPC connection: {}", e);
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl Source for OpcSource {
+    fn poll(&mut self) -> Result<Option<Update>, Box<dyn Error>> {
+        if self.pending.is_empty() {
+            self.accept()?;
+            self.read();
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn parse_complete_messages_only() {
+        let bytes = [1, 0, 0, 6, 1, 2, 3, 4, 5, 6, 2, 0, 0];
+        let (message, len) = parse(&bytes).unwrap();
+        assert_eq!(
+            message,
+            Message {
+                channel: 1,
+                command: SET_PIXEL_COLORS,
+                data: &[1, 2, 3, 4, 5, 6],
+            }
+        );
+        assert_eq!(len, 10);
+        assert_eq!(parse(&bytes[len..]), None);
+        assert_eq!(parse(&bytes[..9]), None);
+    }
+
+    #[test]
+    fn buffer_at_most_one_message() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut connection = Connection {
+            stream,
+            buffer: Vec::new(),
+        };
+        let mut message = vec![1, SET_PIXEL_COLORS, 0xff, 0xff];
+        message.resize(MAX_BUFFER_LEN, 7);
+        sender.write_all(&message).unwrap();
+        sender.write_all(&message).unwrap();
+        while connection.buffer.len() < MAX_BUFFER_LEN {
+            assert!(connection.fill());
+        }
+        assert!(connection.fill());
+        assert_eq!(connection.buffer.len(), MAX_BUFFER_LEN);
+        let (message, len) = parse(&connection.buffer).unwrap();
+        assert_eq!((message.channel, len), (1, MAX_BUFFER_LEN));
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use nannou_osc as osc;
+use osc::{Message, Sender};
+use schatter_lib::auth::Key;
+use schatter_lib::frame::{Fragment, FrameError, Reassembler};
+use schatter_lib::protocol::{
+    FRAME_ADDRESS, PING_ADDRESS, PONG_ADDRESS, SIZE_ADDRESS, STATUS_PORT,
+};
+
+use crate::osc_color_to_rgb8;
+use crate::source::{Pixels, Received, Source, Update};
+use crate::stats::Report;
+
+pub const PORT: u16 = 12345;
+const MTU: usize = 10000;
+const WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Receives the frames schatter-server sends over OSC, on one port per
+/// output.
+pub struct OscSource {
+    receivers: Vec<osc::Receiver>,
+    reassemblers: Vec<Reassembler>,
+    pending: VecDeque<Update>,
+    server: Option<IpAddr>,
+    /// Answers pings, bound on the first one.
+    pong: Option<Sender>,
+    /// Messages are dropped unless they are tagged with this key.
+    key: Option<Key>,
+    unauthenticated: Vec<u64>,
+    /// Packets and fragments which could not be decoded.
+    malformed: Vec<u64>,
+    last_warning: Option<Instant>,
+}
+
+impl OscSource {
+    pub fn bind(ports: &[u16], key: Option<Key>) -> Result<Self, Box<dyn Error>> {
+        let receivers = ports
+            .iter()
+            .map(|port| osc::Receiver::bind_with_mtu(*port, MTU))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(OscSource {
+            reassemblers: receivers.iter().map(|_| Reassembler::new()).collect(),
+            unauthenticated: vec![0; receivers.len()],
+            malformed: vec![0; receivers.len()],
+            receivers,
+            pending: VecDeque::new(),
+            server: None,
+            pong: None,
+            key,
+            last_warning: None,
+        })
+    }
+}
+
+impl Source for OscSource {
+    fn poll(&mut self) -> Result<Option<Update>, Box<dyn Error>> {
+        if let Some(update) = self.pending.pop_front() {
+            return Ok(Some(update));
+        }
+        for (output, receiver) in self.receivers.iter().enumerate() {
+            // Bad packets are counted rather than returned as errors, so a
+            // flood neither drops the rest of the packet and the other outputs
+            // nor floods the log.
+            let (packet, server) = match receiver.try_recv() {
+                Ok(Some((packet, address))) => (packet, address.ip()),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.malformed[output] += 1;
+                    if warning_due(&mut self.last_warning) {
+                        println!(
+                            "Warning: dropped a malformed packet on output {}: {}, {} so far",
+                            output + 1,
+                            e,
+                            self.malformed.iter().sum::<u64>()
+                        );
+                    }
+                    continue;
+                }
+            };
+            for mut message in packet.into_msgs() {
+                if let Some(key) = &self.key {
+                    if !authenticate(key, &mut message) {
+                        self.unauthenticated[output] += 1;
+                        if warning_due(&mut self.last_warning) {
+                            println!(
+                                "Warning: dropped an unauthenticated message from {}, {} so far",
+                                server,
+                                self.unauthenticated.iter().sum::<u64>()
+                            );
+                        }
+                        continue;
+                    }
+                }
+                self.server = Some(server);
+                match message.addr.as_str() {
+                    SIZE_ADDRESS => match get_size(message) {
+                        Some(num_leds) => {
+                            self.pending.push_back(Update::Resize { output, num_leds })
+                        }
+                        None => println!("Ignoring invalid size announcement"),
+                    },
+                    FRAME_ADDRESS => {
+                        let reassembler = &mut self.reassemblers[output];
+                        match get_rgb(output, message, reassembler) {
+                            Ok(Some(received)) => self.pending.push_back(Update::Frame(received)),
+                            Ok(None) => (),
+                            Err(e) => {
+                                self.malformed[output] += 1;
+                                if warning_due(&mut self.last_warning) {
+                                    println!(
+                                        "Warning: dropped a malformed frame from {}: {}, {} so far",
+                                        server,
+                                        e,
+                                        self.malformed.iter().sum::<u64>()
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    PING_ADDRESS => answer_ping(&mut self.pong, output, message, server),
+                    _ => (),
+                }
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    fn sender(&self) -> Option<IpAddr> {
+        self.server
+    }
+
+    fn authenticated(&self) -> bool {
+        self.key.is_some()
+    }
+
+    fn extend_report(&self, output: usize, report: &mut Report) {
+        let reassembler = &self.reassemblers[output];
+        report.reordered = reassembler.stale_frames();
+        report.incomplete = reassembler.incomplete_frames();
+        report.lost_fragments = reassembler.lost_fragments();
+        report.unauthenticated = self.unauthenticated[output];
+        report.malformed = self.malformed[output];
+    }
+}
+
+/// Whether the last warning is long enough ago to print another one.
+fn warning_due(last_warning: &mut Option<Instant>) -> bool {
+    let due = last_warning.is_none_or(|last| last.elapsed() >= WARNING_INTERVAL);
+    if due {
+        *last_warning = Some(Instant::now());
+    }
+    due
+}
+
+/// Checks the tag the server appends to a message with a single argument and
+/// removes it. Frames sent as OSC colors have many and are never tagged.
+fn authenticate(key: &Key, message: &mut Message) -> bool {
+    let Some(args) = message.args.as_mut() else {
+        return false;
+    };
+    let Some(osc::Type::Blob(tag)) = args.pop() else {
+        return false;
+    };
+    let payload = match args.as_slice() {
+        [osc::Type::Blob(bytes)] => bytes.clone(),
+        [osc::Type::Int(value)] => value.to_be_bytes().to_vec(),
+        [osc::Type::Long(value)] => value.to_be_bytes().to_vec(),
+        _ => return false,
+    };
+    key.verify(&message.addr, &payload, &tag)
+}
+
+fn get_size(message: Message) -> Option<usize> {
+    match message.args?.first()? {
+        osc::Type::Int(num_leds) if *num_leds > 0 => Some(*num_leds as usize),
+        _ => None,
+    }
+}
+
+/// Answers a ping of the server as soon as it arrives, so the round trip it
+/// measures does not include the time frames wait to be shown.
+fn answer_ping(pong: &mut Option<Sender>, output: usize, message: Message, server: IpAddr) {
+    let Some([osc::Type::Long(sent)]) = message.args.as_deref() else {
+        return;
+    };
+    if pong.is_none() {
+        *pong = osc::sender().ok();
+    }
+    if let Some(sender) = pong {
+        let args = vec![osc::Type::Long(*sent), osc::Type::Int(output as i32 + 1)];
+        sender
+            .send((PONG_ADDRESS, args), (server, STATUS_PORT))
+            .ok();
+    }
+}
+
+/// Decodes a binary frame fragment sent as blob, returning the frame and its
+/// sequence number once it is complete, or, as a fallback, a frame sent as
+/// one OSC color per LED.
+fn get_rgb(
+    output: usize,
+    message: Message,
+    reassembler: &mut Reassembler,
+) -> Result<Option<Received>, FrameError> {
+    let args = message.args.unwrap_or_default();
+    if let Some(osc::Type::Blob(bytes)) = args.first() {
+        let frame = reassembler.push(Fragment::decode(bytes)?);
+        return Ok(frame.map(|frame| Received {
+            output,
+            sequence: Some(frame.sequence),
+            present_at: frame.present_at,
+            rendered_at: frame.rendered_at,
+            pixels: Pixels::from(frame),
+        }));
+    }
+    Ok(Some(Received {
+        output,
+        sequence: None,
+        present_at: None,
+        rendered_at: None,
+        pixels: Pixels::Rgb8(
+            args.into_iter()
+                .filter_map(|arg| match arg {
+                    osc::Type::Color(color) => Some(osc_color_to_rgb8(color)),
+                    _ => None,
+                })
+                .collect(),
+        ),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size_message(num_leds: i32, tag: Vec<u8>) -> Message {
+        Message {
+            addr: SIZE_ADDRESS.to_string(),
+            args: Some(vec![osc::Type::Int(num_leds), osc::Type::Blob(tag)]),
+        }
+    }
+
+    #[test]
+    fn drop_untagged_messages() {
+        let key = Key::new(b"gallery");
+        let tag = key.tag(SIZE_ADDRESS, &626i32.to_be_bytes());
+        let mut message = size_message(626, tag.clone());
+        assert!(authenticate(&key, &mut message));
+        assert_eq!(get_size(message), Some(626));
+        assert!(!authenticate(&key, &mut size_message(700, tag)));
+        let mut untagged = Message {
+            addr: SIZE_ADDRESS.to_string(),
+            args: Some(vec![osc::Type::Int(626)]),
+        };
+        assert!(!authenticate(&key, &mut untagged));
+    }
+
+    #[test]
+    fn count_malformed_packets_and_go_on() {
+        let mut source = OscSource::bind(&[0, 0], None).unwrap();
+        let port = |output: usize| source.receivers[output].local_addr().unwrap().port();
+        let (first, second) = (port(0), port(1));
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"not osc", ("127.0.0.1", first)).unwrap();
+        osc::sender()
+            .unwrap()
+            .send(
+                (SIZE_ADDRESS, vec![osc::Type::Int(626)]),
+                ("127.0.0.1", second),
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        match source.poll() {
+            Ok(Some(Update::Resize { output, num_leds })) => {
+                assert_eq!((output, num_leds), (1, 626))
+            }
+            _ => panic!("expected the size announced on the second output"),
+        }
+        assert_eq!(source.malformed, [1, 0]);
+    }
+}
@@ -0,0 +1,207 @@
+//! The test patterns `schatter-client test` shows, for checking the wiring,
+//! color order and power of a strip.
+
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use smart_leds::colors::*;
+use smart_leds::RGB8;
+
+use crate::config::Config;
+
+/// LEDs the stripes move by per second.
+const STRIPES_SPEED: f32 = 200.0;
+const STRIPES_LIT: usize = 84;
+const STRIPES_PERIOD: usize = 156;
+/// Rainbow cycles per second.
+const RAINBOW_SPEED: f32 = 0.25;
+/// LEDs the chase moves by per second.
+const CHASE_SPEED: f32 = 10.0;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// White and black stripes running along the strip
+    #[default]
+    Stripes,
+    /// The whole strip red, green, blue and white in turn
+    Rgb,
+    /// A rainbow moving along the strip
+    Rainbow,
+    /// A single LED running along the strip, printing its index
+    Chase,
+    /// The first LED of every stripe green and the last one red
+    Markers,
+    /// Brightness rising from black at the start to white at the end
+    Ramp,
+    /// Every LED blinking its index in binary, one bit per second, after a frame with all LEDs on
+    Ids,
+}
+
+/// A test pattern running on one strip.
+pub struct Animation {
+    pattern: Pattern,
+    speed: f32,
+    /// Index of the first LED of every stripe after the first.
+    stripes: Vec<usize>,
+    start: Instant,
+}
+
+impl Animation {
+    pub fn new(config: &Config) -> Self {
+        Animation {
+            pattern: config.pattern,
+            speed: config.pattern_speed,
+            stripes: config.stripes.clone(),
+            start: Instant::now(),
+        }
+    }
+
+    /// The pattern shown while no stream arrives.
+    pub fn fallback(config: &Config) -> Self {
+        Animation {
+            pattern: config.fallback_pattern,
+            speed: config.fallback_speed,
+            ..Animation::new(config)
+        }
+    }
+
+    pub fn frame(&self, num_leds: usize) -> Vec<RGB8> {
+        self.frame_at(self.start.elapsed(), num_leds)
+    }
+
+    /// What the current frame shows, for patterns which are read off the
+    /// strip.
+    pub fn caption(&self, num_leds: usize) -> Option<String> {
+        let time = self.time(self.start.elapsed());
+        match self.pattern {
+            Pattern::Chase => Some(format!("LED {}", chase_index(time, num_leds))),
+            Pattern::Ids => Some(match id_phase(time, num_leds) {
+                0 => "all LEDs".to_string(),
+                phase => format!("bit {}", phase - 1),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Seconds into the pattern, scaled by its speed.
+    fn time(&self, elapsed: Duration) -> f32 {
+        elapsed.as_secs_f32() * self.speed
+    }
+
+    fn frame_at(&self, elapsed: Duration, num_leds: usize) -> Vec<RGB8> {
+        let time = self.time(elapsed);
+        match self.pattern {
+            Pattern::Stripes => {
+                let offset = (time * STRIPES_SPEED) as usize % STRIPES_PERIOD;
+                (0..num_leds)
+                    .map(|i| match (i + STRIPES_PERIOD - offset) % STRIPES_PERIOD {
+                        position if position < STRIPES_LIT => WHITE,
+                        _ => BLACK,
+                    })
+                    .collect()
+            }
+            Pattern::Rgb => {
+                let color = [RED, LIME, BLUE, WHITE][time as usize % 4];
+                vec![color; num_leds]
+            }
+            Pattern::Rainbow => (0..num_leds)
+                .map(|i| hue(i as f32 / num_leds as f32 + time * RAINBOW_SPEED))
+                .collect(),
+            Pattern::Chase => {
+                let mut leds = vec![BLACK; num_leds];
+                if let Some(led) = leds.get_mut(chase_index(time, num_leds)) {
+                    *led = WHITE;
+                }
+                leds
+            }
+            Pattern::Markers => {
+                let mut leds = vec![BLACK; num_leds];
+                for &start in self.stripes.iter().filter(|&&start| start < num_leds) {
+                    leds[start] = LIME;
+                    if start > 0 {
+                        leds[start - 1] = RED;
+                    }
+                }
+                if let Some(first) = leds.first_mut() {
+                    *first = LIME;
+                }
+                if let Some(last) = leds.last_mut() {
+                    *last = RED;
+                }
+                leds
+            }
+            Pattern::Ramp => (0..num_leds)
+                .map(|i| {
+                    let level = (i * 255 / num_leds.saturating_sub(1).max(1)) as u8;
+                    RGB8::new(level, level, level)
+                })
+                .collect(),
+            Pattern::Ids => match id_phase(time, num_leds) {
+                0 => vec![WHITE; num_leds],
+                phase => (0..num_leds)
+                    .map(|i| match i >> (phase - 1) & 1 {
+                        1 => WHITE,
+                        _ => BLACK,
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+fn chase_index(time: f32, num_leds: usize) -> usize {
+    (time * CHASE_SPEED) as usize % num_leds.max(1)
+}
+
+/// 0 while all LEDs are on, then the number of the bit shown plus one.
+fn id_phase(time: f32, num_leds: usize) -> usize {
+    let bits = usize::BITS - num_leds.saturating_sub(1).leading_zeros();
+    time as usize % (bits as usize + 1)
+}
+
+/// A fully saturated color, with the hue given in turns.
+fn hue(turns: f32) -> RGB8 {
+    let sector = turns.rem_euclid(1.0) * 6.0;
+    let rising = ((sector % 1.0) * 255.0) as u8;
+    let falling = 255 - rising;
+    match sector as u8 {
+        0 => RGB8::new(255, rising, 0),
+        1 => RGB8::new(falling, 255, 0),
+        2 => RGB8::new(0, 255, rising),
+        3 => RGB8::new(0, falling, 255),
+        4 => RGB8::new(rising, 0, 255),
+        _ => RGB8::new(255, 0, falling),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animation(pattern: Pattern) -> Animation {
+        Animation::new(&Config {
+            pattern,
+            stripes: vec![4, 7],
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn mark_stripes() {
+        let leds = animation(Pattern::Markers).frame_at(Duration::ZERO, 10);
+        let expected = [LIME, BLACK, BLACK, RED, LIME, BLACK, RED, LIME, BLACK, RED];
+        assert_eq!(leds, expected);
+    }
+
+    #[test]
+    fn blink_binary_ids() {
+        let ids = animation(Pattern::Ids);
+        assert_eq!(ids.frame_at(Duration::ZERO, 5), vec![WHITE; 5]);
+        let leds = ids.frame_at(Duration::from_millis(2500), 5);
+        assert_eq!(leds, [BLACK, BLACK, WHITE, WHITE, BLACK]);
+        // Three bits for LEDs 0-4, then it starts over.
+        assert_eq!(ids.frame_at(Duration::from_secs(4), 5), vec![WHITE; 5]);
+    }
+}
@@ -0,0 +1,82 @@
+//! Maps the pixels of a frame onto the LEDs of a strip whose wiring does not
+//! follow the layout the frame was drawn for.
+
+use serde::Deserialize;
+
+/// Wiring quirks of a strip. `skip` and `reverse` are given in pixels of the
+/// frame, `dummy` in LEDs of the strip.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Remap {
+    /// Pixels of the frame without an LED, which are not shown.
+    pub skip: Vec<usize>,
+    /// LEDs without a pixel, like dead ones passing the data on, which are
+    /// kept dark.
+    pub dummy: Vec<usize>,
+    /// Ranges of pixels, from the first one to the one after the last, wired
+    /// in the opposite direction.
+    pub reverse: Vec<[usize; 2]>,
+}
+
+impl Remap {
+    /// The pixel of the frame every LED of a strip of `num_leds` shows, if
+    /// any.
+    pub fn table(&self, num_leds: usize) -> Vec<Option<usize>> {
+        let mut pixels = (0..).map(|index| self.reversed(index));
+        (0..num_leds)
+            .map(|led| match self.dummy.contains(&led) {
+                true => None,
+                false => pixels.find(|pixel| !self.skip.contains(pixel)),
+            })
+            .collect()
+    }
+
+    /// The number of LEDs needed to show every pixel of a frame of
+    /// `frame_len`.
+    pub fn strip_len(&self, frame_len: usize) -> usize {
+        let mut shown = (0..frame_len)
+            .filter(|pixel| !self.skip.contains(pixel))
+            .count();
+        let mut num_leds = 0;
+        while shown > 0 {
+            if !self.dummy.contains(&num_leds) {
+                shown -= 1;
+            }
+            num_leds += 1;
+        }
+        num_leds
+    }
+
+    fn reversed(&self, index: usize) -> usize {
+        match self
+            .reverse
+            .iter()
+            .find(|[start, end]| (*start..*end).contains(&index))
+        {
+            Some([start, end]) => start + end - 1 - index,
+            None => index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_pixels_to_leds() {
+        let remap = Remap {
+            skip: vec![3],
+            dummy: vec![1],
+            reverse: vec![[2, 5]],
+        };
+        let table = remap.table(6);
+        assert_eq!(table, [Some(0), None, Some(1), Some(4), Some(2), Some(5)]);
+        assert_eq!(remap.strip_len(6), 6);
+        assert_eq!(remap.strip_len(3), 4);
+
+        let identity = Remap::default();
+        assert_eq!(identity.table(3), [Some(0), Some(1), Some(2)]);
+        assert_eq!(identity.strip_len(3), 3);
+    }
+}
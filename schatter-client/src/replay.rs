@@ -0,0 +1,61 @@
+//! Plays a recording of the frames the server sent instead of receiving
+//! them, so a show runs without the server. The recording is played on the
+//! client's own clock from the time it started, the strips of several clients
+//! do not change together as with `schatter-server replay`.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind};
+use std::path::Path;
+use std::time::Instant;
+
+use schatter_lib::recording::{Player, Reader};
+
+use crate::source::{Pixels, Received, Source, Update};
+
+pub struct ReplaySource {
+    player: Player,
+    /// Stream of the recording every output shows.
+    streams: Vec<String>,
+}
+
+impl ReplaySource {
+    /// Fails unless the recording has frames for at least one of the streams.
+    pub fn open(path: &Path, streams: Vec<String>) -> io::Result<Self> {
+        let mut reader = Reader::new(BufReader::new(File::open(path)?))?;
+        loop {
+            match reader.next_record()? {
+                Some(record) if streams.contains(&record.stream) => break,
+                Some(_) => (),
+                None => {
+                    return Err(io::Error::new(
+                        ErrorKind::NotFound,
+                        format!("no frames for {}", streams.join(", ")),
+                    ))
+                }
+            }
+        }
+        Ok(ReplaySource {
+            player: Player::open(path)?,
+            streams,
+        })
+    }
+}
+
+impl Source for ReplaySource {
+    fn poll(&mut self) -> Result<Option<Update>, Box<dyn Error>> {
+        while let Some(record) = self.player.next_due(Instant::now())? {
+            let Some(output) = self.streams.iter().position(|s| *s == record.stream) else {
+                continue;
+            };
+            return Ok(Some(Update::Frame(Received {
+                output,
+                sequence: Some(record.frame.sequence),
+                present_at: None,
+                rendered_at: None,
+                pixels: Pixels::from(record.frame),
+            })));
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,180 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+use smart_leds::SmartLedsWrite;
+use smart_leds::RGB8;
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+use ws281x_rpi::Ws2812Rpi;
+
+use crate::display;
+
+/// Something a strip of LEDs can be written to.
+pub trait LedSink {
+    fn write(&mut self, leds: &[RGB8]) -> Result<(), Box<dyn Error>>;
+}
+
+/// The sinks which can be selected at runtime.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum SinkKind {
+    Ws281x,
+    Terminal,
+    Null,
+    File(PathBuf),
+}
+
+impl SinkKind {
+    pub fn open(
+        &self,
+        num_leds: usize,
+        pin: i32,
+        dma: i32,
+    ) -> Result<Box<dyn LedSink>, Box<dyn Error>> {
+        match self {
+            SinkKind::Ws281x => open_ws281x(num_leds, pin, dma),
+            SinkKind::Terminal => Ok(Box::new(TerminalSink)),
+            SinkKind::Null => Ok(Box::new(NullSink)),
+            SinkKind::File(path) => Ok(Box::new(FileSink::create(path)?)),
+        }
+    }
+}
+
+impl Default for SinkKind {
+    fn default() -> Self {
+        if cfg!(any(target_arch = "arm", target_arch = "aarch64")) {
+            SinkKind::Ws281x
+        } else {
+            SinkKind::Terminal
+        }
+    }
+}
+
+impl FromStr for SinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ws281x" => Ok(SinkKind::Ws281x),
+            "terminal" => Ok(SinkKind::Terminal),
+            "null" => Ok(SinkKind::Null),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(SinkKind::File(PathBuf::from(path))),
+                _ => Err(format!(
+                    "unknown sink '{}', expected ws281x, terminal, null or file:<path>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for SinkKind {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for SinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkKind::Ws281x => write!(f, "ws281x"),
+            SinkKind::Terminal => write!(f, "terminal"),
+            SinkKind::Null => write!(f, "null"),
+            SinkKind::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+fn open_ws281x(num_leds: usize, pin: i32, dma: i32) -> Result<Box<dyn LedSink>, Box<dyn Error>> {
+    let num_leds = num_leds.try_into()?;
+    let ws = Ws2812Rpi::new(num_leds, pin, dma).map_err(|e| format!("{:?}", e))?;
+    Ok(Box::new(Ws281xSink(ws)))
+}
+
+#[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
+fn open_ws281x(_num_leds: usize, _pin: i32, _dma: i32) -> Result<Box<dyn LedSink>, Box<dyn Error>> {
+    Err("the ws281x sink is only available on a Raspberry Pi".into())
+}
+
+/// Drives a WS2812 strip through the Raspberry Pi's PWM/DMA peripheral.
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+pub struct Ws281xSink(Ws2812Rpi);
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+impl LedSink for Ws281xSink {
+    fn write(&mut self, leds: &[RGB8]) -> Result<(), Box<dyn Error>> {
+        self.0
+            .write(leds.iter().cloned())
+            .map_err(|e| e.to_string().into())
+    }
+}
+
+/// Previews the strip as a row of colored blocks in the terminal.
+pub struct TerminalSink;
+
+impl LedSink for TerminalSink {
+    fn write(&mut self, leds: &[RGB8]) -> Result<(), Box<dyn Error>> {
+        display(leds);
+        Ok(())
+    }
+}
+
+/// Discards every frame.
+pub struct NullSink;
+
+impl LedSink for NullSink {
+    fn write(&mut self, _leds: &[RGB8]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Appends every frame as raw RGB24 to a file, e.g. for
+/// `ffmpeg -f rawvideo -pix_fmt rgb24 -s <num_leds>x1 -i <path>`.
+pub struct FileSink {
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn create(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file = File::create(path)
+            .map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+        Ok(FileSink {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl LedSink for FileSink {
+    fn write(&mut self, leds: &[RGB8]) -> Result<(), Box<dyn Error>> {
+        for led in leds {
+            self.writer.write_all(&[led.r, led.g, led.b])?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sink_kind() {
+        assert_eq!("null".parse(), Ok(SinkKind::Null));
+        assert_eq!(
+            "file:/tmp/frames.rgb".parse(),
+            Ok(SinkKind::File(PathBuf::from("/tmp/frames.rgb")))
+        );
+        assert!("file:".parse::<SinkKind>().is_err());
+        assert!("dmx".parse::<SinkKind>().is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+
+use smart_leds::RGB8;
+
+use schatter_lib::frame::{narrow, widen, Frame, PixelFormat};
+
+use crate::stats::Report;
+
+/// A complete frame for one of the outputs and, if the sender numbered it,
+/// its sequence number.
+pub struct Received {
+    pub output: usize,
+    pub sequence: Option<u32>,
+    /// When to show the frame, on the sender's clock.
+    pub present_at: Option<u64>,
+    /// When the frame was rendered, on the sender's clock.
+    pub rendered_at: Option<u64>,
+    pub pixels: Pixels,
+}
+
+/// The colors of a frame, with the precision they were sent with.
+pub enum Pixels {
+    Rgb8(Vec<RGB8>),
+    Rgb16(Vec<[u16; 3]>),
+}
+
+impl Pixels {
+    pub fn len(&self) -> usize {
+        match self {
+            Pixels::Rgb8(leds) => leds.len(),
+            Pixels::Rgb16(pixels) => pixels.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn into_rgb16(self) -> Vec<[u16; 3]> {
+        match self {
+            Pixels::Rgb8(leds) => leds
+                .iter()
+                .map(|led| [widen(led.r), widen(led.g), widen(led.b)])
+                .collect(),
+            Pixels::Rgb16(pixels) => pixels,
+        }
+    }
+}
+
+/// Keeps the precision the frame was sent with.
+impl From<Frame> for Pixels {
+    fn from(frame: Frame) -> Self {
+        match frame.format {
+            PixelFormat::Rgb8 => Pixels::Rgb8(
+                frame
+                    .pixels
+                    .into_iter()
+                    .map(|p| RGB8::from(p.map(narrow)))
+                    .collect(),
+            ),
+            PixelFormat::Rgb16 => Pixels::Rgb16(frame.pixels),
+        }
+    }
+}
+
+pub enum Update {
+    Frame(Received),
+    /// The sender announced a new number of LEDs for an output.
+    Resize {
+        output: usize,
+        num_leds: usize,
+    },
+}
+
+/// A network protocol the client receives the frames of all its outputs
+/// with.
+pub trait Source {
+    /// Returns the next update if one arrived, without blocking.
+    fn poll(&mut self) -> Result<Option<Update>, Box<dyn Error>>;
+
+    /// The host the last frame came from, if the protocol times its frames
+    /// with the sender's clock.
+    fn sender(&self) -> Option<IpAddr> {
+        None
+    }
+
+    /// Whether frames are only accepted from the holder of a secret. Their
+    /// render time then tells replayed frames apart.
+    fn authenticated(&self) -> bool {
+        false
+    }
+
+    /// Adds protocol specific counters to the statistics of an output.
+    fn extend_report(&self, _output: usize, _report: &mut Report) {}
+}
+
+/// A packet which does not follow the protocol it was received with.
+#[derive(Debug, PartialEq)]
+pub struct ParseError(pub &'static str);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed packet: {}", self.0)
+    }
+}
+
+impl Error for ParseError {}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Gaps in the sequence numbers larger than this mean the server restarted.
+const RESTART_DISTANCE: u32 = 1000;
+/// Weight of a new measurement in the smoothed latency.
+const LATENCY_SMOOTHING: f64 = 0.1;
+
+/// Counts the frames of a stream and measures their rate, jitter and latency.
+pub struct Stats {
+    received: u64,
+    dropped: u64,
+    last_sequence: Option<u32>,
+    last_arrival: Option<Instant>,
+    interval_start: Instant,
+    interval_frames: u64,
+    interval_sum: f64,
+    interval_sum_squares: f64,
+    latency: Option<f64>,
+    expired: u64,
+}
+
+/// A snapshot of the stream statistics. The counters of lost fragments,
+/// reordered frames and unauthenticated and malformed messages are filled in
+/// by the source, the power limited frames by the strip and the late frames
+/// by the interpolator.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub received: u64,
+    pub dropped: u64,
+    pub reordered: u64,
+    pub incomplete: u64,
+    pub lost_fragments: u64,
+    pub unauthenticated: u64,
+    pub malformed: u64,
+    /// Authenticated frames which were rendered too long ago to be shown.
+    pub expired: u64,
+    pub limited: u64,
+    pub late: u64,
+    pub fps: f32,
+    pub jitter_ms: f32,
+    pub latency_ms: Option<f32>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats {
+            received: 0,
+            dropped: 0,
+            last_sequence: None,
+            last_arrival: None,
+            interval_start: Instant::now(),
+            interval_frames: 0,
+            interval_sum: 0.0,
+            interval_sum_squares: 0.0,
+            latency: None,
+            expired: 0,
+        }
+    }
+
+    /// Records a complete frame. Frames sent without a sequence number only
+    /// count towards the rate.
+    pub fn record(&mut self, sequence: Option<u32>) {
+        self.received += 1;
+        if let Some(sequence) = sequence {
+            if let Some(last) = self.last_sequence {
+                let gap = sequence.wrapping_sub(last);
+                if gap > 1 && gap < RESTART_DISTANCE {
+                    self.dropped += (gap - 1) as u64;
+                }
+            }
+            self.last_sequence = Some(sequence);
+        }
+
+        let now = Instant::now();
+        if let Some(last) = self.last_arrival {
+            let interval = (now - last).as_secs_f64();
+            self.interval_sum += interval;
+            self.interval_sum_squares += interval * interval;
+        }
+        self.last_arrival = Some(now);
+        self.interval_frames += 1;
+    }
+
+    /// Records the time from rendering a frame to writing it to the strip.
+    pub fn record_latency(&mut self, latency: Duration) {
+        let latency = latency.as_secs_f64();
+        self.latency = Some(match self.latency {
+            Some(smoothed) => smoothed + (latency - smoothed) * LATENCY_SMOOTHING,
+            None => latency,
+        });
+    }
+
+    /// Records an authenticated frame which was dropped as a replay.
+    pub fn record_expired(&mut self) {
+        self.expired += 1;
+    }
+
+    /// The smoothed time from rendering a frame to writing it to the strip.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency.map(Duration::from_secs_f64)
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Summarises the stream since the last report.
+    pub fn report(&mut self) -> Report {
+        let elapsed = self.interval_start.elapsed().as_secs_f64();
+        let fps = self.interval_frames as f64 / elapsed;
+        let jitter = if self.interval_frames > 1 {
+            let n = self.interval_frames as f64;
+            let mean = self.interval_sum / n;
+            (self.interval_sum_squares / n - mean * mean)
+                .max(0.0)
+                .sqrt()
+        } else {
+            0.0
+        };
+        self.interval_start = Instant::now();
+        self.interval_frames = 0;
+        self.interval_sum = 0.0;
+        self.interval_sum_squares = 0.0;
+
+        Report {
+            received: self.received,
+            dropped: self.dropped,
+            fps: fps as f32,
+            jitter_ms: (jitter * 1000.0) as f32,
+            latency_ms: self.latency.map(|latency| (latency * 1000.0) as f32),
+            expired: self.expired,
+            ..Report::default()
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "received {}, dropped {} ({} incomplete, {} fragments lost), reordered {}, power limited {}, late {}, {:.1} fps, jitter {:.1} ms",
+            self.received,
+            self.dropped,
+            self.incomplete,
+            self.lost_fragments,
+            self.reordered,
+            self.limited,
+            self.late,
+            self.fps,
+            self.jitter_ms
+        )?;
+        if self.unauthenticated > 0 {
+            write!(f, ", unauthenticated {}", self.unauthenticated)?;
+        }
+        if self.malformed > 0 {
+            write!(f, ", malformed {}", self.malformed)?;
+        }
+        if self.expired > 0 {
+            write!(f, ", expired {}", self.expired)?;
+        }
+        if let Some(latency_ms) = self.latency_ms {
+            write!(f, ", latency {:.1} ms", latency_ms)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_gaps_as_dropped() {
+        let mut stats = Stats::new();
+        for sequence in [1, 2, 5, 6, 0] {
+            stats.record(Some(sequence));
+        }
+        let report = stats.report();
+        assert_eq!(report.received, 5);
+        assert_eq!(report.dropped, 2);
+    }
+
+    #[test]
+    fn smooth_latency() {
+        let mut stats = Stats::new();
+        assert_eq!(stats.report().latency_ms, None);
+        stats.record_latency(Duration::from_millis(50));
+        stats.record_latency(Duration::from_millis(150));
+        assert_eq!(stats.latency(), Some(Duration::from_millis(60)));
+        assert_eq!(stats.report().latency_ms, Some(60.0));
+    }
+}
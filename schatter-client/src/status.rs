@@ -0,0 +1,96 @@
+//! Status messages telling the server the client is alive and how the
+//! streams of its outputs are doing.
+
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+use nannou_osc::{self as osc, Connected, Sender, Type};
+use schatter_lib::protocol::STATUS_ADDRESS;
+
+use crate::stats::Stats;
+
+const TEMPERATURE_PATH: &str = "/sys/class/thermal/thermal_zone0/temp";
+const HOSTNAME_PATH: &str = "/etc/hostname";
+
+pub struct Heartbeat {
+    sender: Sender<Connected>,
+    id: String,
+    interval: Duration,
+    start: Instant,
+    last_sent: Instant,
+    /// Frames every output had received at the last status message.
+    last_received: Vec<u64>,
+}
+
+impl Heartbeat {
+    pub fn connect(server: &str, id: String, interval: Duration) -> io::Result<Self> {
+        let sender = osc::sender()?.connect(server)?;
+        let now = Instant::now();
+        Ok(Heartbeat {
+            sender,
+            id,
+            interval,
+            start: now,
+            last_sent: now,
+            last_received: Vec::new(),
+        })
+    }
+
+    pub fn is_due(&self) -> bool {
+        !self.interval.is_zero() && self.last_sent.elapsed() >= self.interval
+    }
+
+    /// Sends one status message per output, with the error which occurred
+    /// since the last ones, if any.
+    pub fn send<'a>(
+        &mut self,
+        outputs: impl Iterator<Item = &'a Stats>,
+        error: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let elapsed = self.last_sent.elapsed().as_secs_f32();
+        self.last_sent = Instant::now();
+        let uptime = self.start.elapsed().as_secs_f32();
+        let temperature = cpu_temperature().unwrap_or(f32::NAN);
+        for (index, stats) in outputs.enumerate() {
+            if self.last_received.len() <= index {
+                self.last_received.push(0);
+            }
+            let received = stats.received() - self.last_received[index];
+            self.last_received[index] = stats.received();
+            let args = vec![
+                Type::String(self.id.clone()),
+                Type::Int(index as i32 + 1),
+                Type::Float(uptime),
+                Type::Float(received as f32 / elapsed),
+                Type::Long(stats.dropped() as i64),
+                Type::Float(temperature),
+                Type::Float(
+                    stats
+                        .latency()
+                        .map_or(f32::NAN, |latency| latency.as_secs_f32() * 1000.0),
+                ),
+                Type::String(error.unwrap_or_default().to_string()),
+            ];
+            self.sender.send((STATUS_ADDRESS, args))?;
+        }
+        Ok(())
+    }
+}
+
+/// The temperature of the CPU in °C, as reported by the kernel.
+pub fn cpu_temperature() -> Option<f32> {
+    let millidegrees: f32 = fs::read_to_string(TEMPERATURE_PATH)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(millidegrees / 1000.0)
+}
+
+/// The host name, which tells the Pis apart if no client id was configured.
+pub fn hostname() -> Option<String> {
+    let hostname = fs::read_to_string(HOSTNAME_PATH).ok()?;
+    Some(hostname.trim().to_string()).filter(|hostname| !hostname.is_empty())
+}
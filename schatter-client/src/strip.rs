@@ -0,0 +1,370 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use smart_leds::colors::BLACK;
+use smart_leds::RGB8;
+
+use schatter_lib::frame::{narrow, widen};
+
+use crate::config::{ColorOrder, Config};
+use crate::remap::Remap;
+use crate::sink::LedSink;
+
+const WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The processing every frame goes through before it is written to a sink.
+/// Frames are processed with 16 bits per channel and dithered down to the 8
+/// bits of the LEDs.
+pub struct Strip {
+    sink: Box<dyn LedSink>,
+    num_leds: usize,
+    remap: Remap,
+    /// The pixel of the frame every LED shows.
+    table: Vec<Option<usize>>,
+    calibration: Calibration,
+    color_order: ColorOrder,
+    brightness: u8,
+    power: PowerLimit,
+    dither: bool,
+    level: u8,
+    frame: Vec<[u16; 3]>,
+    corrected: Vec<[u16; 3]>,
+    /// What each channel lost to rounding in the last write, added to the
+    /// next one.
+    residuals: Vec<[u16; 3]>,
+    buffer: Vec<RGB8>,
+    last_write: Instant,
+    padded: u64,
+    truncated: u64,
+    last_warning: Option<Instant>,
+}
+
+impl Strip {
+    pub fn new(sink: Box<dyn LedSink>, num_leds: usize, config: &Config) -> Self {
+        Strip {
+            sink,
+            num_leds,
+            remap: config.remap.clone(),
+            table: config.remap.table(num_leds),
+            calibration: Calibration::new(config.gamma, config.gain),
+            color_order: config.color_order,
+            brightness: config.brightness,
+            power: PowerLimit::new(config.milliamps, config.max_milliamps),
+            dither: config.dither,
+            level: u8::MAX,
+            frame: vec![[0; 3]; num_leds],
+            corrected: Vec::new(),
+            residuals: vec![[0; 3]; num_leds],
+            buffer: Vec::new(),
+            last_write: Instant::now(),
+            padded: 0,
+            truncated: 0,
+            last_warning: None,
+        }
+    }
+
+    pub fn num_leds(&self) -> usize {
+        self.num_leds
+    }
+
+    /// The number of pixels of the frames which fit the strip.
+    pub fn frame_len(&self) -> usize {
+        self.table.iter().flatten().max().map_or(0, |last| last + 1)
+    }
+
+    /// Shows a frame, padding it with black or truncating it if it does not
+    /// match the length of the strip.
+    pub fn show(&mut self, leds: &[RGB8]) -> Result<(), Box<dyn Error>> {
+        let pixels: Vec<[u16; 3]> = leds
+            .iter()
+            .map(|led| [widen(led.r), widen(led.g), widen(led.b)])
+            .collect();
+        self.show_rgb16(&pixels)
+    }
+
+    /// Shows a frame with 16 bits per channel.
+    pub fn show_rgb16(&mut self, pixels: &[[u16; 3]]) -> Result<(), Box<dyn Error>> {
+        self.frame.clear();
+        self.frame.extend(self.table.iter().map(|pixel| {
+            pixel
+                .and_then(|pixel| pixels.get(pixel))
+                .copied()
+                .unwrap_or([0; 3])
+        }));
+        if self.remap.strip_len(pixels.len()) != self.num_leds {
+            self.count_mismatch(pixels.len());
+        }
+        self.fade(u8::MAX)
+    }
+
+    /// Shows the last frame again, dimmed to `level`.
+    pub fn fade(&mut self, level: u8) -> Result<(), Box<dyn Error>> {
+        self.level = level;
+        let brightness = ((self.brightness as u16 * (level as u16 + 1)) >> 8) as u8;
+        self.corrected.clear();
+        self.corrected.extend(
+            self.frame
+                .iter()
+                .map(|pixel| self.calibration.apply(*pixel))
+                .map(|pixel| scale(pixel, brightness)),
+        );
+        if let Some(limit) = self.power.limit(&self.corrected) {
+            for pixel in self.corrected.iter_mut() {
+                *pixel = scale(*pixel, limit);
+            }
+        }
+        self.buffer.clear();
+        for (pixel, residual) in self.corrected.iter().zip(self.residuals.iter_mut()) {
+            let [r, g, b] = match self.dither {
+                true => dither(*pixel, residual),
+                false => pixel.map(narrow),
+            };
+            self.buffer
+                .push(reorder(RGB8 { r, g, b }, self.color_order));
+        }
+        self.last_write = Instant::now();
+        self.sink.write(&self.buffer)
+    }
+
+    /// Writes the last frame again if nothing was written for `interval`, so
+    /// the rounding errors dithering spreads over time even out while no new
+    /// frame arrives.
+    pub fn refresh(&mut self, interval: Duration) -> Result<(), Box<dyn Error>> {
+        if self.dither && self.level > 0 && self.last_write.elapsed() >= interval {
+            self.fade(self.level)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Number of frames which were dimmed to stay within the power budget.
+    pub fn limited_frames(&self) -> u64 {
+        self.power.limited
+    }
+
+    pub fn blank(&mut self) -> Result<(), Box<dyn Error>> {
+        self.residuals.fill([0; 3]);
+        self.show(&vec![BLACK; self.frame_len()])
+    }
+
+    fn count_mismatch(&mut self, len: usize) {
+        if len < self.frame_len() {
+            self.padded += 1;
+        } else {
+            self.truncated += 1;
+        }
+        if self
+            .last_warning
+            .is_none_or(|last| last.elapsed() >= WARNING_INTERVAL)
+        {
+            self.last_warning = Some(Instant::now());
+            println!(
+                "Warning: got a frame of {} LEDs for a strip of {}, {} padded and {} truncated so far",
+                len,
+                self.frame_len(),
+                self.padded,
+                self.truncated
+            );
+        }
+    }
+}
+
+/// Gamma correction and white balance, looked up in one table per channel.
+pub struct Calibration {
+    tables: [Vec<u16>; 3],
+}
+
+impl Calibration {
+    pub fn new(gamma: f32, gain: [f32; 3]) -> Self {
+        let tables = gain.map(|gain| {
+            (0..=u16::MAX)
+                .map(|value| {
+                    let corrected = (value as f32 / 65535.0).powf(gamma) * gain;
+                    (corrected * 65535.0).round() as u16
+                })
+                .collect()
+        });
+        Calibration { tables }
+    }
+
+    pub fn apply(&self, pixel: [u16; 3]) -> [u16; 3] {
+        [
+            self.tables[0][pixel[0] as usize],
+            self.tables[1][pixel[1] as usize],
+            self.tables[2][pixel[2] as usize],
+        ]
+    }
+}
+
+/// Rounds every channel down to 8 bits, carrying what got lost over to the
+/// next write. A channel between two steps alternates between them, so it
+/// shows its 16 bit value on average.
+fn dither(pixel: [u16; 3], residual: &mut [u16; 3]) -> [u8; 3] {
+    let mut led = [0; 3];
+    for ((channel, residual), led) in pixel.iter().zip(residual.iter_mut()).zip(&mut led) {
+        let value = *channel as u32 + *residual as u32;
+        // At most (65535 + 256) / 257, so it never exceeds 255.
+        let step = value / 257;
+        *residual = (value - step * 257) as u16;
+        *led = step as u8;
+    }
+    led
+}
+
+/// Estimates the current a frame draws and dims frames which would exceed
+/// the budget of the strip's power supply.
+struct PowerLimit {
+    /// Current per step of each color channel.
+    milliamps: [f32; 3],
+    max_milliamps: u32,
+    limited: u64,
+    last_warning: Option<Instant>,
+}
+
+impl PowerLimit {
+    fn new(milliamps: [f32; 3], max_milliamps: u32) -> Self {
+        PowerLimit {
+            milliamps: milliamps.map(|milliamps| milliamps / 65535.0),
+            max_milliamps,
+            limited: 0,
+            last_warning: None,
+        }
+    }
+
+    fn current(&self, pixels: &[[u16; 3]]) -> f32 {
+        let [r, g, b] = self.milliamps;
+        pixels
+            .iter()
+            .map(|p| p[0] as f32 * r + p[1] as f32 * g + p[2] as f32 * b)
+            .sum()
+    }
+
+    /// Returns the brightness the frame has to be scaled to, if it exceeds
+    /// the budget.
+    fn limit(&mut self, pixels: &[[u16; 3]]) -> Option<u8> {
+        if self.max_milliamps == 0 {
+            return None;
+        }
+        let current = self.current(pixels);
+        let max = self.max_milliamps as f32;
+        if current <= max {
+            return None;
+        }
+        // scale() multiplies by (limit + 1) / 256, which must not exceed
+        // max / current.
+        let limit = (max / current * 256.0 - 1.0).floor().max(0.0) as u8;
+        self.limited += 1;
+        if self
+            .last_warning
+            .is_none_or(|last| last.elapsed() >= WARNING_INTERVAL)
+        {
+            self.last_warning = Some(Instant::now());
+            println!(
+                "Warning: frame would draw {:.0} mA of {} mA, dimmed to {}%, {} frames limited so far",
+                current,
+                self.max_milliamps,
+                (limit as u32 + 1) * 100 / 256,
+                self.limited
+            );
+        }
+        Some(limit)
+    }
+}
+
+fn reorder(led: RGB8, color_order: ColorOrder) -> RGB8 {
+    let RGB8 { r, g, b } = led;
+    let (r, g, b) = match color_order {
+        ColorOrder::Rgb => (r, g, b),
+        ColorOrder::Rbg => (r, b, g),
+        ColorOrder::Grb => (g, r, b),
+        ColorOrder::Gbr => (g, b, r),
+        ColorOrder::Brg => (b, r, g),
+        ColorOrder::Bgr => (b, g, r),
+    };
+    RGB8 { r, g, b }
+}
+
+fn scale(pixel: [u16; 3], brightness: u8) -> [u16; 3] {
+    pixel.map(|c| ((c as u32 * (brightness as u32 + 1)) >> 8) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use smart_leds::colors::WHITE;
+
+    use super::*;
+
+    struct RecordingSink(Rc<RefCell<Vec<RGB8>>>);
+
+    impl LedSink for RecordingSink {
+        fn write(&mut self, leds: &[RGB8]) -> Result<(), Box<dyn Error>> {
+            *self.0.borrow_mut() = leds.to_vec();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn fit_frames_to_strip() {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let sink = Box::new(RecordingSink(Rc::clone(&written)));
+        let mut strip = Strip::new(sink, 3, &Config::default());
+
+        strip.show(&[WHITE]).unwrap();
+        assert_eq!(*written.borrow(), vec![WHITE, BLACK, BLACK]);
+        strip.show(&[WHITE; 5]).unwrap();
+        assert_eq!(*written.borrow(), vec![WHITE; 3]);
+        assert_eq!((strip.padded, strip.truncated), (1, 1));
+    }
+
+    #[test]
+    fn limit_power() {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let sink = Box::new(RecordingSink(Rc::clone(&written)));
+        let config = Config {
+            milliamps: [20.0; 3],
+            max_milliamps: 600,
+            ..Config::default()
+        };
+        let mut strip = Strip::new(sink, 20, &config);
+
+        strip.show(&[RGB8::new(255, 0, 0); 20]).unwrap();
+        assert_eq!(strip.limited_frames(), 0);
+        strip.show(&[WHITE; 20]).unwrap();
+        assert_eq!(strip.limited_frames(), 1);
+        let pixels: Vec<[u16; 3]> = written
+            .borrow()
+            .iter()
+            .map(|led| [widen(led.r), widen(led.g), widen(led.b)])
+            .collect();
+        let current = strip.power.current(&pixels);
+        assert!(current <= 600.0 && current > 550.0, "{}", current);
+    }
+
+    #[test]
+    fn dither_between_steps() {
+        let mut residual = [0; 3];
+        let half_step = widen(10) + 128;
+        let written: Vec<[u8; 3]> = (0..4)
+            .map(|_| dither([half_step, widen(255), 0], &mut residual))
+            .collect();
+        assert!(written.iter().any(|led| led[0] == 10));
+        assert!(written.iter().any(|led| led[0] == 11));
+        assert!(written.iter().all(|led| led[1] == 255 && led[2] == 0));
+    }
+
+    #[test]
+    fn calibrate_colors() {
+        let unchanged = Calibration::new(1.0, [1.0; 3]);
+        assert_eq!(unchanged.apply([0, 300, 65535]), [0, 300, 65535]);
+
+        let calibration = Calibration::new(2.2, [1.0, 0.5, 1.0]);
+        assert_eq!(calibration.apply([65535; 3]).map(narrow), [255, 128, 255]);
+        assert_eq!(
+            calibration.apply([widen(128), 0, widen(10)]).map(narrow),
+            [56, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use smart_leds::RGB8;
+
+/// Brightness of the brightest idle frame.
+const IDLE_LEVEL: f32 = 24.0;
+const IDLE_PERIOD: Duration = Duration::from_secs(6);
+
+/// Tracks when the last frame arrived and how far the strip should be faded
+/// out because the stream went quiet.
+pub struct Watchdog {
+    timeout: Duration,
+    fade: Duration,
+    last_frame: Instant,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration, fade: Duration) -> Self {
+        Watchdog {
+            timeout,
+            fade,
+            last_frame: Instant::now(),
+        }
+    }
+
+    pub fn feed(&mut self) {
+        self.last_frame = Instant::now();
+    }
+
+    /// Brightness the last frame should be shown with: 255 until the timeout
+    /// elapsed, then falling to 0 over the fade time. A zero timeout never
+    /// expires.
+    pub fn level(&self) -> u8 {
+        if self.timeout.is_zero() {
+            return u8::MAX;
+        }
+        let silent = self.last_frame.elapsed();
+        match silent.checked_sub(self.timeout) {
+            None => u8::MAX,
+            Some(fading) if fading >= self.fade => 0,
+            Some(fading) => {
+                let left = 1.0 - fading.as_secs_f32() / self.fade.as_secs_f32();
+                (left * u8::MAX as f32) as u8
+            }
+        }
+    }
+
+    /// Whether the timeout elapsed since the last frame.
+    pub fn is_silent(&self) -> bool {
+        self.level() < u8::MAX
+    }
+
+    pub fn expired(&self) -> bool {
+        self.level() == 0
+    }
+
+    /// Time since the fade to black finished.
+    pub fn expired_for(&self) -> Duration {
+        self.last_frame
+            .elapsed()
+            .saturating_sub(self.timeout + self.fade)
+    }
+}
+
+/// A slowly breathing, dim white shown while no server is streaming.
+pub fn idle_frame(num_leds: usize, elapsed: Duration) -> Vec<RGB8> {
+    let phase = elapsed.as_secs_f32() / IDLE_PERIOD.as_secs_f32() * 2.0 * PI;
+    let level = ((1.0 - phase.cos()) / 2.0 * IDLE_LEVEL) as u8;
+    vec![
+        RGB8 {
+            r: level,
+            g: level,
+            b: level
+        };
+        num_leds
+    ]
+}
+
+/// Returns a flag which is cleared once SIGINT, SIGTERM or SIGHUP arrives,
+/// so the caller can blank the strip before exiting.
+pub fn running() -> Arc<AtomicBool> {
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = Arc::clone(&running);
+    ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))
+        .expect("Could not install signal handler");
+    running
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fade_after_timeout() {
+        let mut watchdog = Watchdog::new(Duration::from_secs(10), Duration::from_secs(10));
+        assert_eq!(watchdog.level(), u8::MAX);
+        watchdog.last_frame = Instant::now() - Duration::from_secs(15);
+        assert!((120..=128).contains(&watchdog.level()));
+        watchdog.last_frame = Instant::now() - Duration::from_secs(25);
+        assert!(watchdog.expired());
+        assert!(watchdog.expired_for() >= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn zero_timeout_never_expires() {
+        let mut watchdog = Watchdog::new(Duration::ZERO, Duration::from_secs(1));
+        watchdog.last_frame = Instant::now() - Duration::from_secs(60);
+        assert!(!watchdog.expired());
+    }
+}
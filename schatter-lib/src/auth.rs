@@ -0,0 +1,63 @@
+//! Authenticates the messages of the server with a secret it shares with the
+//! clients, so other hosts on the network can not drive the LEDs. A message
+//! carries the tag of its address and payload as an extra OSC blob: the first
+//! `TAG_LEN` bytes of their HMAC-SHA256.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const TAG_LEN: usize = 16;
+
+/// A shared secret, ready to tag and verify payloads.
+#[derive(Clone)]
+pub struct Key {
+    mac: Hmac<Sha256>,
+}
+
+impl Key {
+    pub fn new(secret: &[u8]) -> Self {
+        Key {
+            mac: Hmac::new_from_slice(secret).expect("HMAC takes keys of any length"),
+        }
+    }
+
+    /// Tags the payload of a message to `address`, so it can not be passed
+    /// off as a message to another address either.
+    pub fn tag(&self, address: &str, payload: &[u8]) -> Vec<u8> {
+        self.mac(address, payload).finalize().into_bytes()[..TAG_LEN].to_vec()
+    }
+
+    /// Compares in constant time, so the tag can not be guessed byte by byte.
+    pub fn verify(&self, address: &str, payload: &[u8], tag: &[u8]) -> bool {
+        tag.len() == TAG_LEN
+            && self
+                .mac(address, payload)
+                .verify_truncated_left(tag)
+                .is_ok()
+    }
+
+    fn mac(&self, address: &str, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = self.mac.clone();
+        mac.update(address.as_bytes());
+        mac.update(&[0]);
+        mac.update(payload);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_forged_tags() {
+        let key = Key::new(b"gallery");
+        let tag = key.tag("/", b"frame");
+        assert_eq!(tag.len(), TAG_LEN);
+        assert!(key.verify("/", b"frame", &tag));
+        assert!(!key.verify("/", b"frame!", &tag));
+        assert!(!key.verify("/size", b"frame", &tag));
+        assert!(!key.verify("/", b"frame", &tag[..TAG_LEN - 1]));
+        assert!(!Key::new(b"guess").verify("/", b"frame", &tag));
+    }
+}
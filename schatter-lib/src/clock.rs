@@ -0,0 +1,148 @@
+//! NTP style synchronisation of the clients' clocks with the server's, so
+//! frames can carry the time all clients show them at.
+//!
+//! A client sends a request with the time it was sent at, the server answers
+//! with the times it received the request and sent the answer at.
+//!
+//! ```text
+//! 0..4   magic "SCHC"
+//! 4      version
+//! 5..13  origin: time the client sent the request at, on its clock
+//! 13..21 receive: time the server received the request at, 0 in requests
+//! 21..29 transmit: time the server sent the reply at, 0 in requests
+//! 29..45 tag of bytes 0..29, see `auth`, in replies of a server sharing a
+//!        secret with the clients
+//! ```
+//!
+//! All times are µs, big endian.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::auth::{Key, TAG_LEN};
+
+pub const MAGIC: [u8; 4] = *b"SCHC";
+pub const VERSION: u8 = 1;
+pub const PACKET_LEN: usize = 29;
+/// Length of a tagged packet.
+pub const TAGGED_LEN: usize = PACKET_LEN + TAG_LEN;
+/// What the tag of a packet is computed for, as if it were sent to this OSC
+/// address.
+const TAG_ADDRESS: &str = "/sync";
+
+/// The server's clock, in µs since the UNIX epoch.
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_micros() as u64)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SyncPacket {
+    pub origin: u64,
+    pub receive: u64,
+    pub transmit: u64,
+}
+
+impl SyncPacket {
+    pub fn request(origin: u64) -> Self {
+        SyncPacket {
+            origin,
+            ..SyncPacket::default()
+        }
+    }
+
+    pub fn encode(&self) -> [u8; PACKET_LEN] {
+        let mut bytes = [0; PACKET_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5..13].copy_from_slice(&self.origin.to_be_bytes());
+        bytes[13..21].copy_from_slice(&self.receive.to_be_bytes());
+        bytes[21..29].copy_from_slice(&self.transmit.to_be_bytes());
+        bytes
+    }
+
+    /// Returns `None` for anything but a sync packet of this version.
+    pub fn decode(bytes: &[u8]) -> Option<SyncPacket> {
+        if bytes.len() != PACKET_LEN || bytes[0..4] != MAGIC || bytes[4] != VERSION {
+            return None;
+        }
+        let time = |start: usize| u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap());
+        Some(SyncPacket {
+            origin: time(5),
+            receive: time(13),
+            transmit: time(21),
+        })
+    }
+
+    /// Encodes the packet, followed by its tag if a secret is shared.
+    pub fn encode_tagged(&self, key: Option<&Key>) -> Vec<u8> {
+        let mut bytes = self.encode().to_vec();
+        if let Some(key) = key {
+            let tag = key.tag(TAG_ADDRESS, &bytes);
+            bytes.extend_from_slice(&tag);
+        }
+        bytes
+    }
+
+    /// Like `decode`, but if a secret is shared the packet has to carry a
+    /// valid tag.
+    pub fn decode_tagged(bytes: &[u8], key: Option<&Key>) -> Option<SyncPacket> {
+        match key {
+            Some(key) => {
+                let (packet, tag) = bytes.split_at_checked(PACKET_LEN)?;
+                key.verify(TAG_ADDRESS, packet, tag)
+                    .then(|| SyncPacket::decode(packet))?
+            }
+            None => SyncPacket::decode(bytes),
+        }
+    }
+
+    /// The offset of the server's clock from the client's and the round trip
+    /// time, given the time the reply arrived at on the client's clock. The
+    /// offset is exact if the request and the reply took equally long.
+    pub fn offset_and_delay(&self, destination: u64) -> (i64, u64) {
+        let outbound = self.receive as i64 - self.origin as i64;
+        let inbound = self.transmit as i64 - destination as i64;
+        let round_trip = destination.saturating_sub(self.origin);
+        let delay = round_trip.saturating_sub(self.transmit.saturating_sub(self.receive));
+        ((outbound + inbound) / 2, delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_offset() {
+        // The server's clock is 1000 µs ahead, each way takes 50 µs.
+        let reply = SyncPacket {
+            origin: 10_000,
+            receive: 11_050,
+            transmit: 11_060,
+        };
+        let bytes = reply.encode();
+        assert_eq!(SyncPacket::decode(&bytes), Some(reply));
+        assert_eq!(SyncPacket::decode(&bytes[1..]), None);
+        assert_eq!(reply.offset_and_delay(10_110), (1000, 100));
+    }
+
+    #[test]
+    fn reject_untagged_replies() {
+        let key = Key::new(b"gallery");
+        let reply = SyncPacket {
+            origin: 10_000,
+            receive: 11_050,
+            transmit: 11_060,
+        };
+        let tagged = reply.encode_tagged(Some(&key));
+        assert_eq!(tagged.len(), TAGGED_LEN);
+        assert_eq!(SyncPacket::decode_tagged(&tagged, Some(&key)), Some(reply));
+        let untagged = reply.encode_tagged(None);
+        assert_eq!(SyncPacket::decode_tagged(&untagged, None), Some(reply));
+        assert_eq!(SyncPacket::decode_tagged(&untagged, Some(&key)), None);
+        let mut forged = tagged.clone();
+        forged[20] ^= 1;
+        assert_eq!(SyncPacket::decode_tagged(&forged, Some(&key)), None);
+    }
+}
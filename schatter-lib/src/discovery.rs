@@ -0,0 +1,121 @@
+//! Binary encoding of the announcement a client broadcasts, so the server can
+//! find it without knowing its address.
+//!
+//! ```text
+//! 0..4   magic "SCHA"
+//! 4      version
+//! 5      length of the client id
+//! 6..    client id, UTF-8
+//! ..     port every output of the client receives frames on, big endian
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+pub const MAGIC: [u8; 4] = *b"SCHA";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 6;
+/// Longer ids are cut off.
+pub const MAX_ID_LEN: usize = u8::MAX as usize;
+
+/// Tells the server which client is reachable at the address the
+/// announcement came from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Announcement {
+    pub id: String,
+    pub ports: Vec<u16>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AnnouncementError {
+    TooShort(usize),
+    BadMagic,
+    UnsupportedVersion(u8),
+    InvalidId,
+    OddPorts,
+}
+
+impl fmt::Display for AnnouncementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnouncementError::TooShort(len) => {
+                write!(f, "announcement of {} bytes is too short", len)
+            }
+            AnnouncementError::BadMagic => write!(f, "not a schatter announcement"),
+            AnnouncementError::UnsupportedVersion(version) => {
+                write!(f, "unsupported announcement version {}", version)
+            }
+            AnnouncementError::InvalidId => write!(f, "client id is not valid UTF-8"),
+            AnnouncementError::OddPorts => write!(f, "ports do not fill whole bytes"),
+        }
+    }
+}
+
+impl Error for AnnouncementError {}
+
+impl Announcement {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut len = self.id.len().min(MAX_ID_LEN);
+        while !self.id.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut bytes = Vec::with_capacity(HEADER_LEN + len + self.ports.len() * 2);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.push(len as u8);
+        bytes.extend_from_slice(&self.id.as_bytes()[..len]);
+        for port in &self.ports {
+            bytes.extend_from_slice(&port.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Announcement, AnnouncementError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(AnnouncementError::TooShort(bytes.len()));
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(AnnouncementError::BadMagic);
+        }
+        if bytes[4] != VERSION {
+            return Err(AnnouncementError::UnsupportedVersion(bytes[4]));
+        }
+        let end = HEADER_LEN + bytes[5] as usize;
+        let id = bytes
+            .get(HEADER_LEN..end)
+            .ok_or(AnnouncementError::TooShort(bytes.len()))?;
+        let id = String::from_utf8(id.to_vec()).map_err(|_| AnnouncementError::InvalidId)?;
+        let ports = &bytes[end..];
+        if !ports.len().is_multiple_of(2) {
+            return Err(AnnouncementError::OddPorts);
+        }
+        let ports = ports
+            .chunks_exact(2)
+            .map(|port| u16::from_be_bytes([port[0], port[1]]))
+            .collect();
+        Ok(Announcement { id, ports })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let announcement = Announcement {
+            id: "raspberrypi-1".to_string(),
+            ports: vec![34254, 34255],
+        };
+        let bytes = announcement.encode();
+        assert_eq!(Announcement::decode(&bytes), Ok(announcement));
+        assert_eq!(
+            Announcement::decode(&bytes[..10]),
+            Err(AnnouncementError::TooShort(10))
+        );
+        assert_eq!(
+            Announcement::decode(&bytes[..bytes.len() - 1]),
+            Err(AnnouncementError::OddPorts)
+        );
+    }
+}
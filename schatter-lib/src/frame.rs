@@ -0,0 +1,528 @@
+//! Binary encoding of a frame. A frame is split into fragments which fit into
+//! a single UDP datagram, each one sent as an OSC blob.
+//!
+//! ```text
+//! 0..4   magic "SCHF"
+//! 4      version
+//! 5      pixel format, 0 = RGB8, 1 = RGB16 big endian
+//! 6..10  sequence number of the frame, big endian
+//! 10     index of this fragment
+//! 11     number of fragments of the frame
+//! 12..14 number of LEDs of the whole frame, big endian
+//! 14..16 index of the first LED in this fragment, big endian
+//! 16..18 number of LEDs in this fragment, big endian
+//! 18..26 time to show the frame at in µs on the server's clock, big endian,
+//!        0 to show it right away
+//! 26..34 time the frame was rendered at in µs on the server's clock, big
+//!        endian, 0 if unknown
+//! 34..   packed pixel data
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+pub const MAGIC: [u8; 4] = *b"SCHF";
+pub const VERSION: u8 = 4;
+pub const HEADER_LEN: usize = 34;
+/// The most LEDs a frame can have.
+pub const MAX_LEDS: usize = u16::MAX as usize;
+/// The most fragments a frame can be split into.
+pub const MAX_FRAGMENTS: usize = u8::MAX as usize;
+
+const FORMAT_RGB8: u8 = 0;
+const FORMAT_RGB16: u8 = 1;
+/// Frames this far behind the newest one mean the sender restarted its count.
+const RESTART_DISTANCE: i32 = 1000;
+
+/// How the color channels of a pixel are sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PixelFormat {
+    #[default]
+    Rgb8,
+    Rgb16,
+}
+
+/// A frame of pixels. Whatever the format it is sent in, pixels are kept with
+/// 16 bits per channel, 8 bit values are scaled to the full range.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    pub sequence: u32,
+    pub format: PixelFormat,
+    /// When to show the frame, see `clock::now_micros`.
+    pub present_at: Option<u64>,
+    /// When the frame was rendered, which the clients measure the latency
+    /// from.
+    pub rendered_at: Option<u64>,
+    pub pixels: Vec<[u16; 3]>,
+}
+
+/// The part of a frame which is sent in one datagram.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fragment {
+    pub sequence: u32,
+    pub format: PixelFormat,
+    pub present_at: Option<u64>,
+    pub rendered_at: Option<u64>,
+    pub index: u8,
+    pub count: u8,
+    pub num_leds: u16,
+    pub offset: u16,
+    pub pixels: Vec<[u16; 3]>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+    TooShort(usize),
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnsupportedFormat(u8),
+    LengthMismatch { expected: usize, actual: usize },
+    OutOfBounds,
+    TooManyLeds(usize),
+    TooManyFragments(usize),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::TooShort(len) => write!(f, "frame of {} bytes is too short", len),
+            FrameError::BadMagic => write!(f, "not a schatter frame"),
+            FrameError::UnsupportedVersion(version) => {
+                write!(f, "unsupported frame version {}", version)
+            }
+            FrameError::UnsupportedFormat(format) => {
+                write!(f, "unsupported pixel format {}", format)
+            }
+            FrameError::LengthMismatch { expected, actual } => write!(
+                f,
+                "frame announces {} bytes of pixel data but carries {}",
+                expected, actual
+            ),
+            FrameError::OutOfBounds => write!(f, "fragment lies outside of its frame"),
+            FrameError::TooManyLeds(num_leds) => write!(
+                f,
+                "frame of {} LEDs is too large, at most {} fit",
+                num_leds, MAX_LEDS
+            ),
+            FrameError::TooManyFragments(count) => write!(
+                f,
+                "frame needs {} fragments, at most {} fit",
+                count, MAX_FRAGMENTS
+            ),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Rgb16 => 6,
+        }
+    }
+
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            PixelFormat::Rgb8 => FORMAT_RGB8,
+            PixelFormat::Rgb16 => FORMAT_RGB16,
+        }
+    }
+
+    pub(crate) fn from_code(code: u8) -> Result<Self, FrameError> {
+        match code {
+            FORMAT_RGB8 => Ok(PixelFormat::Rgb8),
+            FORMAT_RGB16 => Ok(PixelFormat::Rgb16),
+            _ => Err(FrameError::UnsupportedFormat(code)),
+        }
+    }
+}
+
+/// Scales an 8 bit channel to 16 bits, so 255 becomes 65535.
+pub fn widen(value: u8) -> u16 {
+    value as u16 * 257
+}
+
+/// The 8 bit channel closest to a 16 bit one.
+pub fn narrow(value: u16) -> u8 {
+    ((value as u32 + 128) / 257) as u8
+}
+
+impl Frame {
+    pub fn new(sequence: u32, pixels: Vec<[u8; 3]>) -> Self {
+        Frame {
+            sequence,
+            format: PixelFormat::Rgb8,
+            present_at: None,
+            rendered_at: None,
+            pixels: pixels.into_iter().map(|p| p.map(widen)).collect(),
+        }
+    }
+
+    /// A frame sent with 16 bits per channel.
+    pub fn new_rgb16(sequence: u32, pixels: Vec<[u16; 3]>) -> Self {
+        Frame {
+            sequence,
+            format: PixelFormat::Rgb16,
+            present_at: None,
+            rendered_at: None,
+            pixels,
+        }
+    }
+
+    /// Splits the frame into fragments of at most `max_leds` LEDs, unless
+    /// it has more LEDs or needs more fragments than the encoding can count.
+    pub fn fragments(&self, max_leds: usize) -> Result<Vec<Fragment>, FrameError> {
+        if self.pixels.len() > MAX_LEDS {
+            return Err(FrameError::TooManyLeds(self.pixels.len()));
+        }
+        let max_leds = max_leds.max(1);
+        let count = self.pixels.len().div_ceil(max_leds).max(1);
+        if count > MAX_FRAGMENTS {
+            return Err(FrameError::TooManyFragments(count));
+        }
+        Ok((0..count)
+            .map(|index| {
+                let offset = index * max_leds;
+                let end = (offset + max_leds).min(self.pixels.len());
+                Fragment {
+                    sequence: self.sequence,
+                    format: self.format,
+                    present_at: self.present_at,
+                    rendered_at: self.rendered_at,
+                    index: index as u8,
+                    count: count as u8,
+                    num_leds: self.pixels.len() as u16,
+                    offset: offset as u16,
+                    pixels: self.pixels[offset..end].to_vec(),
+                }
+            })
+            .collect())
+    }
+}
+
+impl Fragment {
+    /// # Panics
+    ///
+    /// If the fragment has more than `MAX_LEDS` pixels, which no fragment of
+    /// `Frame::fragments` has.
+    pub fn encode(&self) -> Vec<u8> {
+        let len = u16::try_from(self.pixels.len()).expect("fragment of more than MAX_LEDS");
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.pixels.len() * bytes_per_pixel);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.push(self.format.code());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.push(self.index);
+        bytes.push(self.count);
+        bytes.extend_from_slice(&self.num_leds.to_be_bytes());
+        bytes.extend_from_slice(&self.offset.to_be_bytes());
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.extend_from_slice(&self.present_at.unwrap_or(0).to_be_bytes());
+        bytes.extend_from_slice(&self.rendered_at.unwrap_or(0).to_be_bytes());
+        for pixel in &self.pixels {
+            for channel in pixel {
+                match self.format {
+                    PixelFormat::Rgb8 => bytes.push(narrow(*channel)),
+                    PixelFormat::Rgb16 => bytes.extend_from_slice(&channel.to_be_bytes()),
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Fragment, FrameError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(FrameError::TooShort(bytes.len()));
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(FrameError::BadMagic);
+        }
+        if bytes[4] != VERSION {
+            return Err(FrameError::UnsupportedVersion(bytes[4]));
+        }
+        let format = PixelFormat::from_code(bytes[5])?;
+        let bytes_per_pixel = format.bytes_per_pixel();
+        let sequence = u32::from_be_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+        let index = bytes[10];
+        let count = bytes[11];
+        let num_leds = u16::from_be_bytes([bytes[12], bytes[13]]);
+        let offset = u16::from_be_bytes([bytes[14], bytes[15]]);
+        let len = u16::from_be_bytes([bytes[16], bytes[17]]) as usize;
+        let time = |start: usize| {
+            let time = u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap());
+            Some(time).filter(|time| *time != 0)
+        };
+        let data = &bytes[HEADER_LEN..];
+        if data.len() != len * bytes_per_pixel {
+            return Err(FrameError::LengthMismatch {
+                expected: len * bytes_per_pixel,
+                actual: data.len(),
+            });
+        }
+        if index >= count || offset as usize + len > num_leds as usize {
+            return Err(FrameError::OutOfBounds);
+        }
+        let pixels = match format {
+            PixelFormat::Rgb8 => data
+                .chunks_exact(3)
+                .map(|p| [widen(p[0]), widen(p[1]), widen(p[2])])
+                .collect(),
+            PixelFormat::Rgb16 => data
+                .chunks_exact(6)
+                .map(|p| {
+                    [
+                        u16::from_be_bytes([p[0], p[1]]),
+                        u16::from_be_bytes([p[2], p[3]]),
+                        u16::from_be_bytes([p[4], p[5]]),
+                    ]
+                })
+                .collect(),
+        };
+        Ok(Fragment {
+            sequence,
+            format,
+            present_at: time(18),
+            rendered_at: time(26),
+            index,
+            count,
+            num_leds,
+            offset,
+            pixels,
+        })
+    }
+}
+
+/// Collects the fragments of a frame until it is complete. A frame is
+/// discarded as incomplete as soon as a fragment of a newer frame arrives.
+#[derive(Default)]
+pub struct Reassembler {
+    frame: Option<Frame>,
+    completed: Option<u32>,
+    received: Vec<bool>,
+    stale: Option<u32>,
+    incomplete_frames: u64,
+    lost_fragments: u64,
+    stale_frames: u64,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Reassembler::default()
+    }
+
+    /// Adds a fragment and returns the frame once all its fragments arrived.
+    pub fn push(&mut self, fragment: Fragment) -> Option<Frame> {
+        if let Some(completed) = self.completed {
+            let age = fragment.sequence.wrapping_sub(completed) as i32;
+            if age < -RESTART_DISTANCE {
+                *self = Reassembler {
+                    incomplete_frames: self.incomplete_frames,
+                    lost_fragments: self.lost_fragments,
+                    stale_frames: self.stale_frames,
+                    ..Reassembler::default()
+                };
+            } else if age <= 0 {
+                self.count_stale(fragment.sequence);
+                return None;
+            }
+        }
+        if let Some(frame) = &self.frame {
+            let age = fragment.sequence.wrapping_sub(frame.sequence) as i32;
+            if age < 0 {
+                self.count_stale(fragment.sequence);
+                return None;
+            }
+            // A fragment of the same frame in another shape means the sender
+            // changed its settings, its fragments are not joined with the
+            // earlier ones.
+            let reshaped = self.received.len() != fragment.count as usize
+                || frame.format != fragment.format
+                || frame.pixels.len() != fragment.num_leds as usize;
+            if age > 0 || reshaped {
+                self.discard();
+            }
+        }
+        let frame = self.frame.get_or_insert_with(|| Frame {
+            sequence: fragment.sequence,
+            format: fragment.format,
+            present_at: fragment.present_at,
+            rendered_at: fragment.rendered_at,
+            pixels: vec![[0; 3]; fragment.num_leds as usize],
+        });
+        let offset = fragment.offset as usize;
+        frame.pixels[offset..offset + fragment.pixels.len()].copy_from_slice(&fragment.pixels);
+        self.received.resize(fragment.count as usize, false);
+        self.received[fragment.index as usize] = true;
+        if self.received.iter().all(|received| *received) {
+            self.received.clear();
+            self.completed = Some(fragment.sequence);
+            self.frame.take()
+        } else {
+            None
+        }
+    }
+
+    fn discard(&mut self) {
+        self.incomplete_frames += 1;
+        self.lost_fragments += self.received.iter().filter(|r| !**r).count() as u64;
+        self.received.clear();
+        self.frame = None;
+    }
+
+    fn count_stale(&mut self, sequence: u32) {
+        if self.stale != Some(sequence) {
+            self.stale = Some(sequence);
+            self.stale_frames += 1;
+        }
+    }
+
+    /// Number of frames which were dropped because fragments went missing.
+    pub fn incomplete_frames(&self) -> u64 {
+        self.incomplete_frames
+    }
+
+    /// Number of fragments missing from the dropped frames.
+    pub fn lost_fragments(&self) -> u64 {
+        self.lost_fragments
+    }
+
+    /// Number of frames which were discarded because they arrived after a
+    /// newer one.
+    pub fn stale_frames(&self) -> u64 {
+        self.stale_frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(sequence: u32, num_leds: usize) -> Frame {
+        let pixels = (0..num_leds).map(|i| [i as u8, 0, 255]).collect();
+        Frame::new(sequence, pixels)
+    }
+
+    #[test]
+    fn round_trip() {
+        let fragment = frame(7, 3).fragments(100).unwrap().remove(0);
+        let bytes = fragment.encode();
+        assert_eq!(bytes.len(), HEADER_LEN + 9);
+        assert_eq!(Fragment::decode(&bytes), Ok(fragment));
+    }
+
+    #[test]
+    fn round_trip_rgb16() {
+        let mut frame = Frame::new_rgb16(3, vec![[1, 256, 65535], [0, 2, 4]]);
+        frame.present_at = Some(1_700_000_000_040_000);
+        frame.rendered_at = Some(1_700_000_000_000_000);
+        let fragment = frame.fragments(100).unwrap().remove(0);
+        let bytes = fragment.encode();
+        assert_eq!(bytes.len(), HEADER_LEN + 12);
+        assert_eq!(Fragment::decode(&bytes), Ok(fragment));
+        assert_eq!(narrow(widen(7)), 7);
+    }
+
+    #[test]
+    fn reject_malformed_fragments() {
+        let mut bytes = frame(0, 4).fragments(100).unwrap()[0].encode();
+        assert_eq!(Fragment::decode(&bytes[..5]), Err(FrameError::TooShort(5)));
+        assert_eq!(
+            Fragment::decode(&bytes[..bytes.len() - 1]),
+            Err(FrameError::LengthMismatch {
+                expected: 12,
+                actual: 11
+            })
+        );
+        bytes[4] = VERSION + 1;
+        assert_eq!(
+            Fragment::decode(&bytes),
+            Err(FrameError::UnsupportedVersion(VERSION + 1))
+        );
+        bytes[0] = b'X';
+        assert_eq!(Fragment::decode(&bytes), Err(FrameError::BadMagic));
+    }
+
+    #[test]
+    fn refuse_frames_beyond_the_limits() {
+        let fragments = frame(0, MAX_LEDS).fragments(400).unwrap();
+        let last = fragments.last().unwrap();
+        assert_eq!(Fragment::decode(&last.encode()).as_ref(), Ok(last));
+        assert_eq!((last.offset, last.num_leds), (65200, u16::MAX));
+        assert_eq!(
+            frame(0, MAX_LEDS + 1).fragments(400),
+            Err(FrameError::TooManyLeds(MAX_LEDS + 1))
+        );
+        let fragments = frame(0, MAX_FRAGMENTS).fragments(1).unwrap();
+        assert_eq!(fragments.last().unwrap().count, u8::MAX);
+        assert_eq!(
+            frame(0, MAX_FRAGMENTS + 1).fragments(1),
+            Err(FrameError::TooManyFragments(MAX_FRAGMENTS + 1))
+        );
+    }
+
+    #[test]
+    fn reassemble_out_of_order_fragments() {
+        let frame = frame(1, 10);
+        let mut fragments = frame.fragments(4).unwrap();
+        assert_eq!(fragments.len(), 3);
+        fragments.reverse();
+
+        let mut reassembler = Reassembler::new();
+        let last = fragments.pop().unwrap();
+        for fragment in fragments {
+            assert_eq!(reassembler.push(fragment), None);
+        }
+        assert_eq!(reassembler.push(last), Some(frame));
+    }
+
+    #[test]
+    fn discard_incomplete_frames() {
+        let mut reassembler = Reassembler::new();
+        let first = frame(1, 10).fragments(4).unwrap();
+        let second = frame(2, 10);
+        reassembler.push(first[0].clone());
+        let mut complete = None;
+        for fragment in second.fragments(4).unwrap() {
+            complete = reassembler.push(fragment);
+        }
+        assert_eq!(complete, Some(second));
+        assert_eq!(reassembler.push(first[1].clone()), None);
+        assert_eq!(reassembler.push(first[2].clone()), None);
+        assert_eq!(reassembler.incomplete_frames(), 1);
+        assert_eq!(reassembler.lost_fragments(), 2);
+        assert_eq!(reassembler.stale_frames(), 1);
+    }
+
+    #[test]
+    fn restart_frames_changing_shape() {
+        let mut reassembler = Reassembler::new();
+        let rgb8 = frame(1, 10).fragments(5).unwrap();
+        let rgb16 = Frame::new_rgb16(1, vec![[1, 2, 3]; 10]);
+        let longer = frame(1, 12).fragments(6).unwrap();
+        assert_eq!(reassembler.push(rgb8[0].clone()), None);
+        let fragments = rgb16.fragments(5).unwrap();
+        assert_eq!(reassembler.push(fragments[1].clone()), None);
+        assert_eq!(reassembler.push(longer[0].clone()), None);
+        assert_eq!(reassembler.push(rgb8[1].clone()), None);
+        assert_eq!(reassembler.incomplete_frames(), 3);
+        assert_eq!(reassembler.push(rgb8[0].clone()), Some(frame(1, 10)));
+    }
+
+    #[test]
+    fn follow_restarted_sender() {
+        let mut reassembler = Reassembler::new();
+        let old = frame(5000, 3);
+        let restarted = frame(0, 3);
+        assert_eq!(
+            reassembler.push(old.fragments(10).unwrap().remove(0)),
+            Some(old)
+        );
+        assert_eq!(
+            reassembler.push(restarted.fragments(10).unwrap().remove(0)),
+            Some(restarted)
+        );
+    }
+}
@@ -1,3 +1,10 @@
+pub mod auth;
+pub mod clock;
+pub mod discovery;
+pub mod frame;
+pub mod protocol;
+pub mod recording;
+
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
@@ -0,0 +1,33 @@
+//! OSC addresses shared by schatter-server and schatter-client.
+
+/// Carries the colors of all LEDs of one client.
+pub const FRAME_ADDRESS: &str = "/";
+
+/// Announces the number of LEDs the server is about to send, as a single int.
+pub const SIZE_ADDRESS: &str = "/size";
+
+/// Sent by the clients every few seconds, one message per output: the client
+/// id (string), the output (int, counting from 1), the uptime in seconds
+/// (float), frames per second (float), dropped frames (long), the CPU
+/// temperature in °C (float, NaN if unknown), the mean time from rendering a
+/// frame to writing it to the strip in ms (float, NaN if unknown) and the
+/// last error since the previous message (string, empty if none).
+pub const STATUS_ADDRESS: &str = "/status";
+
+/// Sent by the server to the port of every output, with the time it was sent
+/// at in µs on the server's clock (long).
+pub const PING_ADDRESS: &str = "/ping";
+
+/// The answer of a client to a ping, sent to `STATUS_PORT` right away: the
+/// time of the ping (long) and the output it arrived at (int, counting from
+/// 1).
+pub const PONG_ADDRESS: &str = "/pong";
+
+/// Port the server receives status messages and pongs on.
+pub const STATUS_PORT: u16 = 34250;
+
+/// Port the clients broadcast their `discovery::Announcement` to.
+pub const DISCOVERY_PORT: u16 = 34249;
+
+/// Port the server answers `clock::SyncPacket` requests on.
+pub const SYNC_PORT: u16 = 34251;
@@ -0,0 +1,405 @@
+//! Recordings of the frames the server sends, which replay a show without
+//! the camera and the tracking. Every output of a client is a stream named
+//! `<client id>:<output>`. A frame is stored as its changes to the previous
+//! frame of its stream, so LEDs which stay the same take almost no space.
+//!
+//! ```text
+//! 0..4   magic "SCHR"
+//! 4      version
+//! 5..    records:
+//!        0..8  time since the first record in µs, big endian
+//!        8     length of the stream name
+//!        9..   stream name, UTF-8
+//!        ..+4  sequence number of the frame, big endian
+//!        ..+1  pixel format, as in `frame`
+//!        ..+2  number of LEDs, big endian
+//!        ..+4  length of the changes, big endian
+//!        ..    changes: runs of a number of unchanged LEDs and a number of
+//!              changed ones, both u16 big endian, followed by the colors
+//!              of the changed ones in the pixel format
+//! ```
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::frame::{narrow, widen, Frame, PixelFormat};
+
+pub const MAGIC: [u8; 4] = *b"SCHR";
+pub const VERSION: u8 = 2;
+pub const HEADER_LEN: usize = 5;
+/// Shortest time before a loop starts over, e.g. for a single frame.
+const MIN_INTERVAL: Duration = Duration::from_millis(20);
+/// The counts of unchanged and changed LEDs starting every run.
+const RUN_HEADER_LEN: usize = 4;
+
+/// A frame of one stream and when it was sent. The frame is not stamped, it
+/// is shown whenever it is replayed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub time: Duration,
+    pub stream: String,
+    pub frame: Frame,
+}
+
+pub struct Writer<W: Write> {
+    writer: W,
+    /// Time of the first record, which the others are stored relative to.
+    origin: Option<u64>,
+    /// Last frame of every stream, as it is stored.
+    previous: HashMap<String, Vec<[u16; 3]>>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(Writer {
+            writer,
+            origin: None,
+            previous: HashMap::new(),
+        })
+    }
+
+    /// Appends a frame sent at `time` µs, e.g. `clock::now_micros`, in the
+    /// pixel format it was sent with. Every record is written at once, so a
+    /// recording cut short by a crash only loses the last one, which the
+    /// reader skips.
+    pub fn write(&mut self, time: u64, stream: &str, frame: &Frame) -> io::Result<()> {
+        let num_leds = u16::try_from(frame.pixels.len())
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "frame has too many LEDs"))?;
+        let origin = *self.origin.get_or_insert(time);
+        let mut len = stream.len().min(u8::MAX as usize);
+        while !stream.is_char_boundary(len) {
+            len -= 1;
+        }
+        let stream = &stream[..len];
+        let pixels: Vec<[u16; 3]> = match frame.format {
+            PixelFormat::Rgb8 => frame
+                .pixels
+                .iter()
+                .map(|p| p.map(|c| widen(narrow(c))))
+                .collect(),
+            PixelFormat::Rgb16 => frame.pixels.clone(),
+        };
+        let previous = self.previous.entry(stream.to_string()).or_default();
+        previous.resize(pixels.len(), [0; 3]);
+        let changes = encode_changes(previous, &pixels, frame.format);
+        *previous = pixels;
+
+        let mut bytes = Vec::with_capacity(24 + len + changes.len());
+        bytes.extend_from_slice(&time.saturating_sub(origin).to_be_bytes());
+        bytes.push(len as u8);
+        bytes.extend_from_slice(stream.as_bytes());
+        bytes.extend_from_slice(&frame.sequence.to_be_bytes());
+        bytes.push(frame.format.code());
+        bytes.extend_from_slice(&num_leds.to_be_bytes());
+        bytes.extend_from_slice(&(changes.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&changes);
+        self.writer.write_all(&bytes)
+    }
+}
+
+/// Runs of unchanged and changed pixels, with the colors of the changed ones.
+fn encode_changes(previous: &[[u16; 3]], pixels: &[[u16; 3]], format: PixelFormat) -> Vec<u8> {
+    let mut changes = Vec::new();
+    // Number of pixels from `start` on which are the same or all differ.
+    let run = |start: usize, same: bool| {
+        pixels[start..]
+            .iter()
+            .zip(&previous[start..])
+            .take_while(|(pixel, previous)| (pixel == previous) == same)
+            .take(u16::MAX as usize)
+            .count()
+    };
+    let mut index = 0;
+    while index < pixels.len() {
+        let unchanged = run(index, true);
+        index += unchanged;
+        let changed = run(index, false);
+        changes.extend_from_slice(&(unchanged as u16).to_be_bytes());
+        changes.extend_from_slice(&(changed as u16).to_be_bytes());
+        for pixel in &pixels[index..index + changed] {
+            for channel in pixel {
+                match format {
+                    PixelFormat::Rgb8 => changes.push(narrow(*channel)),
+                    PixelFormat::Rgb16 => changes.extend_from_slice(&channel.to_be_bytes()),
+                }
+            }
+        }
+        index += changed;
+    }
+    changes
+}
+
+/// Applies the changes of a record to the previous frame of its stream.
+fn apply_changes(pixels: &mut [[u16; 3]], changes: &[u8], format: PixelFormat) -> io::Result<()> {
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let mut index = 0;
+    let mut rest = changes;
+    while !rest.is_empty() {
+        let [a, b, c, d, ..] = *rest else {
+            return Err(invalid("changes end within a run".to_string()));
+        };
+        let unchanged = u16::from_be_bytes([a, b]) as usize;
+        let changed = u16::from_be_bytes([c, d]) as usize;
+        index += unchanged;
+        rest = &rest[RUN_HEADER_LEN..];
+        let colors = rest
+            .get(..changed * bytes_per_pixel)
+            .filter(|_| index + changed <= pixels.len())
+            .ok_or_else(|| invalid("changes lie outside of the frame".to_string()))?;
+        for (pixel, color) in pixels[index..]
+            .iter_mut()
+            .zip(colors.chunks_exact(bytes_per_pixel))
+        {
+            *pixel = match format {
+                PixelFormat::Rgb8 => [widen(color[0]), widen(color[1]), widen(color[2])],
+                PixelFormat::Rgb16 => [
+                    u16::from_be_bytes([color[0], color[1]]),
+                    u16::from_be_bytes([color[2], color[3]]),
+                    u16::from_be_bytes([color[4], color[5]]),
+                ],
+            };
+        }
+        index += changed;
+        rest = &rest[changed * bytes_per_pixel..];
+    }
+    Ok(())
+}
+
+pub struct Reader<R: Read> {
+    reader: R,
+    /// Last frame of every stream, which the next one changes.
+    previous: HashMap<String, Vec<[u16; 3]>>,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        if header[0..4] != MAGIC {
+            return Err(invalid("not a schatter recording".to_string()));
+        }
+        if header[4] != VERSION {
+            return Err(invalid(format!(
+                "unsupported recording version {}",
+                header[4]
+            )));
+        }
+        Ok(Reader {
+            reader,
+            previous: HashMap::new(),
+        })
+    }
+
+    /// The next record, or `None` at the end of the recording. A record cut
+    /// short ends it as well.
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        match self.read_record() {
+            Ok(record) => Ok(Some(record)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_record(&mut self) -> io::Result<Record> {
+        let mut time = [0; 8];
+        self.reader.read_exact(&mut time)?;
+        let mut len = [0; 1];
+        self.reader.read_exact(&mut len)?;
+        let mut stream = vec![0; len[0] as usize];
+        self.reader.read_exact(&mut stream)?;
+        let stream = String::from_utf8(stream)
+            .map_err(|_| invalid("stream name is not valid UTF-8".to_string()))?;
+        let mut header = [0; 11];
+        self.reader.read_exact(&mut header)?;
+        let sequence = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let format = PixelFormat::from_code(header[4]).map_err(|e| invalid(e.to_string()))?;
+        let num_leds = u16::from_be_bytes([header[5], header[6]]) as usize;
+        let len = u32::from_be_bytes([header[7], header[8], header[9], header[10]]) as usize;
+        // Every run covers at least one LED, so a corrupt length is caught
+        // before it is allocated.
+        if len > num_leds * (RUN_HEADER_LEN + format.bytes_per_pixel()) {
+            return Err(invalid(format!(
+                "changes of {} bytes are too long for {} LEDs",
+                len, num_leds
+            )));
+        }
+        let mut changes = vec![0; len];
+        self.reader.read_exact(&mut changes)?;
+        let mut pixels = self.previous.get(&stream).cloned().unwrap_or_default();
+        pixels.resize(num_leds, [0; 3]);
+        apply_changes(&mut pixels, &changes, format)?;
+        self.previous.insert(stream.clone(), pixels.clone());
+        Ok(Record {
+            time: Duration::from_micros(u64::from_be_bytes(time)),
+            stream,
+            frame: Frame {
+                sequence,
+                format,
+                present_at: None,
+                rendered_at: None,
+                pixels,
+            },
+        })
+    }
+}
+
+impl<R: Read + Seek> Reader<R> {
+    /// Starts over at the first record.
+    pub fn rewind(&mut self) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(HEADER_LEN as u64))?;
+        self.previous.clear();
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Plays a recording in a loop with its original timing.
+pub struct Player {
+    reader: Reader<BufReader<File>>,
+    start: Instant,
+    next: Option<Record>,
+    /// Time between the last two records, which the first record of the next
+    /// loop follows the last one after.
+    last_interval: Duration,
+}
+
+impl Player {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = Reader::new(BufReader::new(File::open(path)?))?;
+        let next = reader.next_record()?;
+        Ok(Player {
+            reader,
+            start: Instant::now(),
+            next,
+            last_interval: Duration::ZERO,
+        })
+    }
+
+    /// The next record once it is due at `now`. Never returns a record for
+    /// an empty recording.
+    pub fn next_due(&mut self, now: Instant) -> io::Result<Option<Record>> {
+        let start = self.start;
+        let Some(record) = self.next.take_if(|next| start + next.time <= now) else {
+            return Ok(None);
+        };
+        self.next = self.reader.next_record()?;
+        match &self.next {
+            Some(next) => self.last_interval = next.time.saturating_sub(record.time),
+            None => {
+                self.start += record.time + self.last_interval.max(MIN_INTERVAL);
+                self.reader.rewind()?;
+                self.next = self.reader.next_record()?;
+            }
+        }
+        Ok(Some(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let frame = Frame::new_rgb16(4, vec![[1, 2, 3], [65535, 0, 256]]);
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(5_000_000, "raspberrypi-1:1", &frame).unwrap();
+        writer.write(5_020_000, "raspberrypi-1:2", &frame).unwrap();
+
+        let mut reader = Reader::new(Cursor::new(writer.writer)).unwrap();
+        let first = reader.next_record().unwrap().unwrap();
+        assert_eq!(first.time, Duration::ZERO);
+        assert_eq!(first.stream, "raspberrypi-1:1");
+        assert_eq!(first.frame, frame);
+        let second = reader.next_record().unwrap().unwrap();
+        assert_eq!(second.time, Duration::from_millis(20));
+        assert_eq!(second.frame, frame);
+        assert_eq!(reader.next_record().unwrap(), None);
+        reader.rewind().unwrap();
+        assert_eq!(reader.next_record().unwrap(), Some(first));
+    }
+
+    #[test]
+    fn store_only_changed_leds() {
+        let mut pixels = vec![[0, 0, 0]; 1000];
+        pixels[10] = [255, 128, 0];
+        let first = Frame::new(1, pixels.clone());
+        pixels[10] = [0, 0, 0];
+        pixels[500] = [1, 2, 3];
+        pixels[501] = [4, 5, 6];
+        let second = Frame::new(2, pixels);
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(0, "a:1", &first).unwrap();
+        writer.write(20_000, "a:1", &second).unwrap();
+        writer.write(40_000, "a:1", &second).unwrap();
+        assert!(writer.writer.len() < 150);
+
+        let mut reader = Reader::new(Cursor::new(writer.writer)).unwrap();
+        assert_eq!(reader.next_record().unwrap().unwrap().frame, first);
+        assert_eq!(reader.next_record().unwrap().unwrap().frame, second);
+        assert_eq!(
+            reader.next_record().unwrap().unwrap().frame.pixels,
+            second.pixels
+        );
+        reader.rewind().unwrap();
+        assert_eq!(reader.next_record().unwrap().unwrap().frame, first);
+    }
+
+    #[test]
+    fn end_at_a_record_cut_short() {
+        let frame = Frame::new(1, vec![[255, 0, 0]; 10]);
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(0, "a:1", &frame).unwrap();
+        let complete = writer.writer.len();
+        writer.write(20_000, "a:1", &frame).unwrap();
+
+        for cut in [complete + 4, complete + 12, writer.writer.len() - 1] {
+            let mut reader = Reader::new(Cursor::new(&writer.writer[..cut])).unwrap();
+            assert!(reader.next_record().unwrap().is_some());
+            assert_eq!(reader.next_record().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn reject_overlong_changes() {
+        let frame = Frame::new(1, vec![[255, 0, 0]; 10]);
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(0, "a:1", &frame).unwrap();
+        let len = HEADER_LEN + 19;
+        writer.writer[len..len + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let mut reader = Reader::new(Cursor::new(writer.writer)).unwrap();
+        let error = reader.next_record().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn loop_with_original_timing() {
+        let name = format!("schatter-loop-{}.rec", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let frame = Frame::new(0, vec![[255, 0, 0]]);
+        let mut writer = Writer::new(File::create(&path).unwrap()).unwrap();
+        writer.write(0, "a:1", &frame).unwrap();
+        writer.write(30_000, "a:1", &frame).unwrap();
+
+        let mut player = Player::open(&path).unwrap();
+        let start = player.start;
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(player.next_due(at(0)).unwrap().is_some());
+        assert_eq!(player.next_due(at(29)).unwrap(), None);
+        assert!(player.next_due(at(30)).unwrap().is_some());
+        assert_eq!(player.next_due(at(59)).unwrap(), None);
+        let looped = player.next_due(at(60)).unwrap().unwrap();
+        assert_eq!(looped.time, Duration::ZERO);
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,300 @@
+//! Receives the status messages and pongs of the clients, keeps what they
+//! report for the status view and logs the clients which come up, report
+//! errors or go quiet.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nannou_osc as osc;
+use nannou_osc::Type;
+use schatter_lib::clock::now_micros;
+use schatter_lib::protocol::{PONG_ADDRESS, STATUS_ADDRESS, STATUS_PORT};
+
+/// A client which did not report for this long is considered down.
+const SILENCE: Duration = Duration::from_secs(15);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Time between the logged latencies of all clients.
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The outputs of all clients, by the address of the client and the number
+/// of the output.
+pub type Clients = Arc<Mutex<BTreeMap<(IpAddr, i32), ClientStatus>>>;
+
+/// What an output of a client reported last.
+pub struct ClientStatus {
+    /// Empty until the first status message, if a pong came first.
+    pub id: String,
+    pub last_seen: Instant,
+    pub uptime: f32,
+    pub fps: f32,
+    pub dropped: i64,
+    pub temperature: f32,
+    /// From rendering a frame to writing it to the strip, NaN if unknown.
+    pub latency_ms: f32,
+    /// Network round trip of the last ping.
+    pub round_trip: Option<Duration>,
+    pub error: String,
+}
+
+struct Status {
+    id: String,
+    output: i32,
+    uptime: f32,
+    fps: f32,
+    dropped: i64,
+    temperature: f32,
+    latency_ms: f32,
+    error: String,
+}
+
+impl Status {
+    fn parse(message: &osc::Message) -> Option<Status> {
+        if message.addr != STATUS_ADDRESS {
+            return None;
+        }
+        match message.args.as_deref()? {
+            [Type::String(id), Type::Int(output), Type::Float(uptime), Type::Float(fps), Type::Long(dropped), Type::Float(temperature), Type::Float(latency_ms), Type::String(error)] => {
+                Some(Status {
+                    id: id.clone(),
+                    output: *output,
+                    uptime: *uptime,
+                    fps: *fps,
+                    dropped: *dropped,
+                    temperature: *temperature,
+                    latency_ms: *latency_ms,
+                    error: error.clone(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ClientStatus {
+    fn new(id: String) -> Self {
+        ClientStatus {
+            id,
+            last_seen: Instant::now(),
+            uptime: 0.0,
+            fps: 0.0,
+            dropped: 0,
+            temperature: f32::NAN,
+            latency_ms: f32::NAN,
+            round_trip: None,
+            error: String::new(),
+        }
+    }
+
+    fn update(&mut self, status: Status) {
+        self.id = status.id;
+        self.last_seen = Instant::now();
+        self.uptime = status.uptime;
+        self.fps = status.fps;
+        self.dropped = status.dropped;
+        self.temperature = status.temperature;
+        self.latency_ms = status.latency_ms;
+        if !status.error.is_empty() {
+            self.error = status.error;
+        }
+    }
+
+    fn round_trip_ms(&self) -> f32 {
+        self.round_trip
+            .map_or(f32::NAN, |round_trip| round_trip.as_secs_f32() * 1000.0)
+    }
+}
+
+/// The time a pong took to come back and the output which sent it.
+fn parse_pong(message: &osc::Message) -> Option<(Duration, i32)> {
+    if message.addr != PONG_ADDRESS {
+        return None;
+    }
+    match message.args.as_deref()? {
+        [Type::Long(sent), Type::Int(output)] => {
+            let round_trip = now_micros().checked_sub(*sent as u64)?;
+            Some((Duration::from_micros(round_trip), *output))
+        }
+        _ => None,
+    }
+}
+
+/// One line per output of every client, for the status view.
+pub fn table(clients: &BTreeMap<(IpAddr, i32), ClientStatus>) -> String {
+    let mut table = format!(
+        "{:<16} {:>6} {:<15} {:>6} {:>8} {:>11} {:>9} {:>8}  last error\n",
+        "client", "output", "address", "fps", "dropped", "round trip", "latency", "CPU"
+    );
+    for ((address, output), status) in clients {
+        writeln!(
+            table,
+            "{:<16} {:>6} {:<15} {:>6.1} {:>8} {:>8.1} ms {:>6.1} ms {:>5.1} °C  {}",
+            status.id,
+            output,
+            address,
+            status.fps,
+            status.dropped,
+            status.round_trip_ms(),
+            status.latency_ms,
+            status.temperature,
+            status.error
+        )
+        .ok();
+    }
+    table
+}
+
+/// Removes the outputs which did not report for `SILENCE`.
+fn forget_quiet(clients: &mut BTreeMap<(IpAddr, i32), ClientStatus>) {
+    clients.retain(|(address, output), status| {
+        let alive = status.last_seen.elapsed() < SILENCE;
+        if !alive {
+            println!(
+                "Client {} output {} at {} went quiet",
+                status.id, output, address
+            );
+        }
+        alive
+    });
+}
+
+pub fn spawn_receiver() -> Clients {
+    let clients = Clients::default();
+    let table = Arc::clone(&clients);
+    thread::spawn(move || {
+        let receiver = match osc::Receiver::bind(STATUS_PORT) {
+            Ok(receiver) => receiver,
+            Err(e) => {
+                eprintln!(
+                    "Could not receive client status on port {}: {}",
+                    STATUS_PORT, e
+                );
+                return;
+            }
+        };
+        let mut last_log = Instant::now();
+        loop {
+            let mut clients = table.lock().unwrap();
+            while let Ok(Some((packet, address))) = receiver.try_recv() {
+                for message in packet.into_msgs() {
+                    if let Some((round_trip, output)) = parse_pong(&message) {
+                        let key = (address.ip(), output);
+                        let status = clients
+                            .entry(key)
+                            .or_insert_with(|| ClientStatus::new(String::new()));
+                        status.round_trip = Some(round_trip);
+                        status.last_seen = Instant::now();
+                    }
+                    let Some(status) = Status::parse(&message) else {
+                        continue;
+                    };
+                    let key = (address.ip(), status.output);
+                    if clients.get(&key).is_none_or(|known| known.id.is_empty()) {
+                        println!(
+                            "Client {} output {} at {} is up for {:.0}s, {:.1} fps, {} dropped, CPU at {:.1} °C",
+                            status.id,
+                            status.output,
+                            address,
+                            status.uptime,
+                            status.fps,
+                            status.dropped,
+                            status.temperature
+                        );
+                    }
+                    if !status.error.is_empty() {
+                        println!(
+                            "Client {} output {}: {}",
+                            status.id, status.output, status.error
+                        );
+                    }
+                    clients
+                        .entry(key)
+                        .or_insert_with(|| ClientStatus::new(status.id.clone()))
+                        .update(status);
+                }
+            }
+            forget_quiet(&mut clients);
+            if last_log.elapsed() >= LOG_INTERVAL {
+                last_log = Instant::now();
+                for ((_, output), status) in clients.iter() {
+                    println!(
+                        "Client {} output {}: round trip {:.1} ms, latency {:.1} ms",
+                        status.id,
+                        output,
+                        status.round_trip_ms(),
+                        status.latency_ms
+                    );
+                }
+            }
+            drop(clients);
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+    clients
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn message(addr: &str, args: Vec<Type>) -> osc::Message {
+        osc::Message {
+            addr: addr.to_string(),
+            args: Some(args),
+        }
+    }
+
+    #[test]
+    fn parse_status_messages() {
+        let args = vec![
+            Type::String("pi-left".to_string()),
+            Type::Int(2),
+            Type::Float(60.0),
+            Type::Float(29.5),
+            Type::Long(3),
+            Type::Float(48.2),
+            Type::Float(41.0),
+            Type::String(String::new()),
+        ];
+        let status = Status::parse(&message(STATUS_ADDRESS, args.clone())).unwrap();
+        assert_eq!((status.id.as_str(), status.output), ("pi-left", 2));
+        assert_eq!((status.fps, status.dropped), (29.5, 3));
+        assert_eq!(status.latency_ms, 41.0);
+        assert!(Status::parse(&message(PONG_ADDRESS, args.clone())).is_none());
+        assert!(Status::parse(&message(STATUS_ADDRESS, args[..7].to_vec())).is_none());
+    }
+
+    #[test]
+    fn parse_pongs() {
+        let sent = now_micros() - 5_000;
+        let pong = message(PONG_ADDRESS, vec![Type::Long(sent as i64), Type::Int(1)]);
+        let (round_trip, output) = parse_pong(&pong).unwrap();
+        assert_eq!(output, 1);
+        assert!(round_trip >= Duration::from_millis(5));
+        let early = message(
+            PONG_ADDRESS,
+            vec![Type::Long(now_micros() as i64 + 1_000_000), Type::Int(1)],
+        );
+        assert_eq!(parse_pong(&early), None);
+        let untimed = message(PONG_ADDRESS, vec![Type::Int(1)]);
+        assert_eq!(parse_pong(&untimed), None);
+        assert_eq!(parse_pong(&message(STATUS_ADDRESS, vec![])), None);
+    }
+
+    #[test]
+    fn forget_clients_after_silence() {
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mut clients = BTreeMap::new();
+        clients.insert((address, 1), ClientStatus::new("pi-left".to_string()));
+        let mut quiet = ClientStatus::new("pi-right".to_string());
+        quiet.last_seen = Instant::now() - SILENCE;
+        clients.insert((address, 2), quiet);
+        forget_quiet(&mut clients);
+        assert_eq!(clients.keys().collect::<Vec<_>>(), [&(address, 1)]);
+    }
+}
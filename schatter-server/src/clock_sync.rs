@@ -0,0 +1,48 @@
+//! Answers the clock synchronisation requests of the clients with the time of
+//! the server, which the presentation times of the frames are given in. The
+//! replies are tagged with the secret shared with the clients, if any, so
+//! other hosts can not skew their clocks.
+
+use std::net::{Ipv4Addr, UdpSocket};
+use std::thread;
+
+use schatter_lib::auth::Key;
+use schatter_lib::clock::{now_micros, SyncPacket, PACKET_LEN};
+use schatter_lib::protocol::SYNC_PORT;
+
+pub fn spawn_responder(key: Option<Key>) {
+    thread::spawn(move || {
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, SYNC_PORT)) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!(
+                    "Could not answer clock requests on port {}: {}",
+                    SYNC_PORT, e
+                );
+                return;
+            }
+        };
+        let mut packet = [0; PACKET_LEN + 1];
+        loop {
+            let (len, address) = match socket.recv_from(&mut packet) {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("Could not receive clock request: {}", e);
+                    continue;
+                }
+            };
+            let receive = now_micros();
+            let Some(request) = SyncPacket::decode(&packet[..len]) else {
+                continue;
+            };
+            let reply = SyncPacket {
+                origin: request.origin,
+                receive,
+                transmit: now_micros(),
+            };
+            socket
+                .send_to(&reply.encode_tagged(key.as_ref()), address)
+                .ok();
+        }
+    });
+}
@@ -0,0 +1,120 @@
+//! Finds the clients announcing themselves on the local network and assigns
+//! them the LEDs configured for their id.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::ops::Range;
+use std::path::Path;
+use std::thread;
+
+use csv::Reader;
+use schatter_lib::discovery::Announcement;
+use schatter_lib::frame::MAX_LEDS;
+use schatter_lib::protocol::DISCOVERY_PORT;
+
+const MAX_PACKET_LEN: usize = 1500;
+
+/// The LEDs one output of a client shows.
+#[derive(Clone, Debug)]
+pub struct Assignment {
+    pub id: String,
+    /// Output of the client, counting from 1.
+    pub output: usize,
+    pub led_range: Range<usize>,
+}
+
+/// Reads the assignments from a CSV file with the columns client id, output,
+/// first LED and the LED after the last one.
+pub fn parse_assignments(path: &Path) -> Result<Vec<Assignment>, Box<dyn Error>> {
+    let mut rdr = Reader::from_path(path)?;
+    let mut assignments = Vec::new();
+    for result in rdr.records() {
+        let record = result?;
+        let line = record.position().map_or(0, |position| position.line());
+        let number = |index: usize, column: &str| -> Result<usize, String> {
+            let value = record.get(index).unwrap_or_default();
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("line {}: {} {:?} is not a number", line, column, value))
+        };
+        let output = number(1, "output")?;
+        let start = number(2, "first LED")?;
+        let end = number(3, "end")?;
+        if start > end {
+            return Err(format!(
+                "line {}: LEDs {}..{} end before they start",
+                line, start, end
+            )
+            .into());
+        }
+        if end - start > MAX_LEDS {
+            return Err(format!(
+                "line {}: LEDs {}..{} are more than the {} a client can be sent",
+                line, start, end, MAX_LEDS
+            )
+            .into());
+        }
+        assignments.push(Assignment {
+            id: record.get(0).unwrap_or_default().to_string(),
+            output,
+            led_range: start..end,
+        });
+    }
+    Ok(assignments)
+}
+
+/// Listens for announcements and calls `found` with the address and the
+/// assignment of every assigned output of the announcing client. Clients announce
+/// themselves periodically, so `found` is called again and again.
+pub fn spawn_listener(
+    assignments: Vec<Assignment>,
+    mut found: impl FnMut(SocketAddr, &Assignment) + Send + 'static,
+) {
+    thread::spawn(move || {
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!(
+                    "Could not listen for clients on port {}: {}",
+                    DISCOVERY_PORT, e
+                );
+                return;
+            }
+        };
+        let mut unknown = HashSet::new();
+        let mut packet = [0; MAX_PACKET_LEN];
+        loop {
+            let (len, address) = match socket.recv_from(&mut packet) {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("Could not receive announcement: {}", e);
+                    continue;
+                }
+            };
+            let Ok(announcement) = Announcement::decode(&packet[..len]) else {
+                continue;
+            };
+            let mut assigned = assignments
+                .iter()
+                .filter(|assignment| assignment.id == announcement.id)
+                .peekable();
+            if assigned.peek().is_none() && unknown.insert(announcement.id.clone()) {
+                println!(
+                    "Client {} at {} has no LEDs assigned",
+                    announcement.id, address
+                );
+            }
+            for assignment in assigned {
+                let port = assignment
+                    .output
+                    .checked_sub(1)
+                    .and_then(|index| announcement.ports.get(index));
+                if let Some(port) = port {
+                    found(SocketAddr::new(address.ip(), *port), assignment);
+                }
+            }
+        }
+    });
+}
@@ -1,29 +1,83 @@
 mod camera_wrapper;
+mod client_status;
+mod clock_sync;
 mod collision_detector;
+mod discovery;
 mod monolith;
 mod motion_tracker;
+mod replay;
 
+use std::env;
 use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process;
 
-use nannou::prelude::{Key, Update};
+use nannou::prelude::{Key, Update, BLACK, WHITE};
 use nannou::{App, Frame, LoopMode};
 use parry3d::na::{Isometry3, Point3, Vector3};
 use parry3d::query::PointQuery;
 use parry3d::shape::{Cone, Cuboid};
 
 use crate::camera_wrapper::CameraWrapper;
+use crate::client_status::Clients;
+use crate::discovery::Assignment;
 use crate::monolith::Monolith;
 use crate::motion_tracker::VideoProcessor;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::io::Write;
 
+const PATH_CLIENTS_FILE: &str = "./clients.csv";
+const PATH_SECRET_FILE: &str = "./secret.txt";
+
 fn main() {
-    nannou::app(model).update(update).run();
+    match mode() {
+        Mode::Replay(path) => {
+            let key = Monolith::load_key(Path::new(PATH_SECRET_FILE));
+            clock_sync::spawn_responder(key.clone());
+            client_status::spawn_receiver();
+            replay::run(&path, assignments(), key);
+        }
+        Mode::Render | Mode::Record(_) => nannou::app(model).update(update).run(),
+    }
+}
+
+enum Mode {
+    Render,
+    /// Render and write the frames sent to the clients to a file.
+    Record(PathBuf),
+    /// Send the frames of a recording instead of rendering.
+    Replay(PathBuf),
+}
+
+fn mode() -> Mode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.as_slice() {
+        [] => Mode::Render,
+        [command, path] if command == "record" => Mode::Record(path.into()),
+        [command, path] if command == "replay" => Mode::Replay(path.into()),
+        _ => {
+            eprintln!("usage: schatter-server [record <file> | replay <file>]");
+            process::exit(2);
+        }
+    }
+}
+
+/// The LEDs assigned to the outputs of the clients.
+fn assignments() -> Vec<Assignment> {
+    let path = Path::new(PATH_CLIENTS_FILE);
+    match discovery::parse_assignments(path) {
+        Ok(assignments) => assignments,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
 }
 
 struct Model {
     monolith: Monolith,
+    clients: Clients,
 }
 
 fn model(app: &App) -> Model {
@@ -36,9 +90,28 @@ fn model(app: &App) -> Model {
         .key_pressed(key_pressed)
         .build()
         .unwrap();
+    app.new_window()
+        .title("Clients")
+        .size(900, 300)
+        .view(view_clients)
+        .build()
+        .unwrap();
 
+    let recording = match mode() {
+        Mode::Record(path) => Some(path),
+        _ => None,
+    };
+    let key = Monolith::load_key(Path::new(PATH_SECRET_FILE));
+    clock_sync::spawn_responder(key.clone());
     Model {
-        monolith: Monolith::new(app, window_id_monolith),
+        monolith: Monolith::new(
+            app,
+            window_id_monolith,
+            recording.as_deref(),
+            assignments(),
+            key,
+        ),
+        clients: client_status::spawn_receiver(),
     }
 }
 
@@ -70,3 +143,19 @@ fn view2(_app: &App, model: &Model, frame: Frame) {
         .texture_reshaper
         .encode_render_pass(frame.texture_view(), &mut *encoder);
 }
+
+/// Lists the clients with their frame rate and latencies.
+fn view_clients(app: &App, model: &Model, frame: Frame) {
+    let draw = app.draw();
+    draw.background().color(BLACK);
+    let table = client_status::table(&model.clients.lock().unwrap());
+    let rect = frame.rect().pad(10.0);
+    draw.text(&table)
+        .xy(rect.xy())
+        .wh(rect.wh())
+        .font_size(12)
+        .left_justify()
+        .align_text_top()
+        .color(WHITE);
+    draw.to_frame(app, &frame).unwrap();
+}
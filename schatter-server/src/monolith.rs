@@ -1,14 +1,18 @@
-use std::io::Write;
-use std::net::TcpStream;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::num::NonZeroU32;
 use std::ops::{Add, Range};
 use std::path::Path;
+use std::process;
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use csv::Reader;
+use futures::executor::block_on;
 use nannou::draw::properties::ColorScalar;
-use nannou::image::{ImageBuffer, Pixel};
 use nannou::lyon::geom::euclid::Trig;
 use nannou::prelude::float::FloatCore;
 use nannou::prelude::*;
@@ -20,8 +24,14 @@ use nannou_osc::Type;
 use osc::{Color, Connected, Sender};
 use parry3d::math::{Real, Vector};
 use parry3d::na::{Point3, Rotation3};
+use schatter_lib::auth::Key;
+use schatter_lib::clock::now_micros;
+use schatter_lib::frame::{narrow, Frame, PixelFormat};
+use schatter_lib::protocol::{FRAME_ADDRESS, PING_ADDRESS, SIZE_ADDRESS};
+use schatter_lib::recording::Writer;
 
 use crate::collision_detector::CollisionDetector;
+use crate::discovery::{self, Assignment};
 use crate::motion_tracker::VideoProcessor;
 
 const WIDTH: f32 = 1460.0;
@@ -38,19 +48,282 @@ const FLEEING_TIME: Duration = Duration::new(3, 0);
 // const RASPBERRY_PI_ADDRESS: &str = "127.0.0.1:34254";
 const RASPBERRY_PI_ADDRESS: &str = "192.168.1.186:34254";
 const NUM_LEDS_TO_SEND: usize = 2 * NUM_LED_SIDE;
+const FRAME_ENCODING: FrameEncoding = FrameEncoding::Blob;
+const PIXEL_FORMAT: PixelFormat = PixelFormat::Rgb16;
+// Keeps each fragment below the 1472 byte UDP payload of an Ethernet frame, so
+// losing one IP fragment does not take the whole datagram with it.
+const MAX_FRAGMENT_BYTES: usize = 1200;
+/// Frames are stamped to be shown this long after they were read back, so
+/// every client has received them by then and the strips change together.
+pub(crate) const PRESENTATION_DELAY: Duration = Duration::from_millis(40);
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+/// Frames which can be read back at once. While all are in flight, frames
+/// are rendered without being sent.
+const READBACK_BUFFERS: usize = 3;
+/// Bytes of an `Rgba16Float` pixel.
+const READBACK_PIXEL_BYTES: u32 = 8;
+
+/// How the colors of a frame are packed into the OSC message.
+enum FrameEncoding {
+    /// A binary `schatter_lib::frame::Frame`, one blob per fragment
+    Blob,
+    /// One OSC color per LED, understood by older clients. Never tagged, so
+    /// clients sharing a secret drop these frames.
+    Color,
+}
 
-struct Client {
+pub(crate) struct Client {
     sender: Sender<Connected>,
+    address: SocketAddr,
     led_range: Range<usize>,
+    /// `<client id>:<output>`, the name of the frames in a recording.
+    pub(crate) stream: String,
+    sequence: u32,
+    last_ping: Instant,
+    /// Tags every message if a secret is shared with the clients.
+    key: Option<Key>,
 }
 
 impl Client {
-    fn new(address: &str, led_range: Range<usize>) -> Self {
-        let sender = osc::sender()
-            .expect("Could not bind to default socket")
-            .connect(address)
-            .expect("Could not connect to socket at address");
-        Client { sender, led_range }
+    fn new(address: SocketAddr, assignment: &Assignment, key: Option<Key>) -> io::Result<Self> {
+        let sender = osc::sender()?.connect(address)?;
+        let client = Client {
+            sender,
+            address,
+            led_range: assignment.led_range.clone(),
+            stream: format!("{}:{}", assignment.id, assignment.output),
+            sequence: 0,
+            last_ping: Instant::now(),
+            key,
+        };
+        client.announce_size();
+        Ok(client)
+    }
+
+    /// Tells the client to reinitialise its strip for the length of `led_range`.
+    fn announce_size(&self) {
+        let num_leds = self.led_range.len() as i32;
+        self.send(SIZE_ADDRESS, Type::Int(num_leds), &num_leds.to_be_bytes());
+    }
+
+    /// Asks the client for a pong, which tells the network round trip.
+    fn ping(&mut self) {
+        self.last_ping = Instant::now();
+        let sent = now_micros() as i64;
+        self.send(PING_ADDRESS, Type::Long(sent), &sent.to_be_bytes());
+    }
+
+    /// Sends a message with a single argument, `payload` being its bytes,
+    /// followed by their tag if a secret is shared with the client.
+    fn send(&self, address: &str, arg: Type, payload: &[u8]) {
+        let mut args = vec![arg];
+        if let Some(key) = &self.key {
+            args.push(Type::Blob(key.tag(address, payload)));
+        }
+        self.sender.send((address, args)).ok();
+    }
+
+    /// Sends the pixels and returns the frame they were sent as.
+    pub(crate) fn send_frame(
+        &mut self,
+        pixels: Vec<[u16; 3]>,
+        rendered_at: u64,
+        present_at: u64,
+    ) -> Frame {
+        let mut frame = match PIXEL_FORMAT {
+            PixelFormat::Rgb8 => {
+                let pixels = pixels.iter().map(|p| p.map(narrow)).collect();
+                Frame::new(self.sequence, pixels)
+            }
+            PixelFormat::Rgb16 => Frame::new_rgb16(self.sequence, pixels),
+        };
+        frame.rendered_at = Some(rendered_at);
+        frame.present_at = Some(present_at);
+        match FRAME_ENCODING {
+            FrameEncoding::Blob => {
+                let max_leds = MAX_FRAGMENT_BYTES / PIXEL_FORMAT.bytes_per_pixel();
+                match frame.fragments(max_leds) {
+                    Ok(fragments) => {
+                        for fragment in fragments {
+                            let bytes = fragment.encode();
+                            self.send(FRAME_ADDRESS, Type::Blob(bytes.clone()), &bytes);
+                        }
+                    }
+                    Err(e) => eprintln!("Could not send a frame to {}: {}", self.address, e),
+                }
+                self.sequence = self.sequence.wrapping_add(1);
+            }
+            FrameEncoding::Color => {
+                let args = frame
+                    .pixels
+                    .iter()
+                    .map(|p| {
+                        Type::Color(Color {
+                            red: narrow(p[0]),
+                            green: narrow(p[1]),
+                            blue: narrow(p[2]),
+                            alpha: u8::MAX,
+                        })
+                    })
+                    .collect();
+                self.sender.send((FRAME_ADDRESS, args)).ok();
+            }
+        }
+        frame
+    }
+}
+
+/// The clients found on the network, each sent the LEDs assigned to it.
+pub(crate) fn spawn_client_tracker(
+    assignments: Vec<Assignment>,
+    key: Option<Key>,
+) -> Arc<Mutex<Vec<Client>>> {
+    let client_configs: Arc<Mutex<Vec<Client>>> = Arc::new(Mutex::new(Vec::new()));
+    let clients = Arc::clone(&client_configs);
+    discovery::spawn_listener(assignments, move |address, assignment| {
+        let mut clients = clients.lock().unwrap();
+        let led_range = &assignment.led_range;
+        match clients
+            .iter_mut()
+            .find(|client| client.led_range == *led_range)
+        {
+            Some(client) if client.address == address => (),
+            Some(client) => match Client::new(address, assignment, key.clone()) {
+                Ok(moved) => {
+                    println!("LEDs {:?} moved to {}", led_range, address);
+                    *client = moved;
+                }
+                Err(e) => eprintln!("Could not send LEDs {:?} to {}: {}", led_range, address, e),
+            },
+            None => match Client::new(address, assignment, key.clone()) {
+                Ok(client) => {
+                    println!("Sending LEDs {:?} to {}", led_range, address);
+                    clients.push(client);
+                }
+                Err(e) => eprintln!("Could not send LEDs {:?} to {}: {}", led_range, address, e),
+            },
+        }
+    });
+    client_configs
+}
+
+/// A frame copied into a readback buffer, waiting to be mapped and sent.
+struct Snapshot {
+    buffer: wgpu::Buffer,
+    rendered_at: u64,
+}
+
+/// Reads the LED colors of the rendered frames back and sends them to the
+/// clients on a single thread, so they are sent and numbered in the order
+/// they were rendered. Its buffers are reused once the frame in them is sent.
+struct Readback {
+    snapshots: mpsc::Sender<Snapshot>,
+    free: mpsc::Receiver<wgpu::Buffer>,
+    allocated: usize,
+    /// Bytes of a row of the texture, padded as copies into buffers require.
+    padded_row: u32,
+    height: u32,
+}
+
+impl Readback {
+    fn spawn(
+        texture: &wgpu::Texture,
+        led_coordinates: Vec<Point2>,
+        clients: Arc<Mutex<Vec<Client>>>,
+        mut recording: Option<Writer<File>>,
+    ) -> Self {
+        let [width, height] = texture.size();
+        let row = width * READBACK_PIXEL_BYTES;
+        let padded_row = row + wgpu::compute_row_padding(row);
+        let (snapshots, received) = mpsc::channel::<Snapshot>();
+        let (returned, free) = mpsc::channel();
+        thread::spawn(move || {
+            for snapshot in received {
+                let slice = snapshot.buffer.slice(..);
+                // The buffer is mapped once nannou polls the device after the
+                // frame was submitted.
+                match block_on(slice.map_async(wgpu::MapMode::Read)) {
+                    Ok(()) => {
+                        let bytes = slice.get_mapped_range();
+                        let present_at = now_micros() + PRESENTATION_DELAY.as_micros() as u64;
+                        for client in clients.lock().unwrap().iter_mut() {
+                            let pixels = get_pixels(
+                                &led_coordinates[client.led_range.clone()],
+                                &bytes,
+                                padded_row,
+                            );
+                            let frame = client.send_frame(pixels, snapshot.rendered_at, present_at);
+                            let Some(writer) = recording.as_mut() else {
+                                continue;
+                            };
+                            if let Err(e) =
+                                writer.write(snapshot.rendered_at, &client.stream, &frame)
+                            {
+                                eprintln!("Stopped recording: {}", e);
+                                recording = None;
+                            }
+                        }
+                        drop(bytes);
+                        snapshot.buffer.unmap();
+                    }
+                    Err(e) => eprintln!("Could not read the frame back: {}", e),
+                }
+                if returned.send(snapshot.buffer).is_err() {
+                    return;
+                }
+            }
+        });
+        Readback {
+            snapshots,
+            free,
+            allocated: 0,
+            padded_row,
+            height,
+        }
+    }
+
+    /// A buffer to copy the next frame into, unless all are in flight.
+    fn buffer(&mut self, device: &wgpu::Device) -> Option<wgpu::Buffer> {
+        if let Ok(buffer) = self.free.try_recv() {
+            return Some(buffer);
+        }
+        if self.allocated == READBACK_BUFFERS {
+            return None;
+        }
+        self.allocated += 1;
+        Some(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("LED readback"),
+            size: self.padded_row as u64 * self.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        }))
+    }
+}
+
+/// The colors at the LED positions of a frame read back into a buffer with
+/// rows of `padded_row` bytes.
+fn get_pixels(led_coordinates: &[Point2], bytes: &[u8], padded_row: u32) -> Vec<[u16; 3]> {
+    led_coordinates
+        .iter()
+        .map(|coordinate| {
+            let offset = coordinate.y as usize * padded_row as usize
+                + coordinate.x as usize * READBACK_PIXEL_BYTES as usize;
+            // Rgba16Float channels, read as their raw bits.
+            let channel = |index: usize| {
+                let start = offset + index * 2;
+                half_to_srgb16(u16::from_le_bytes([bytes[start], bytes[start + 1]]))
+            };
+            [channel(0), channel(1), channel(2)]
+        })
+        .collect()
+}
+
+/// Pings every client which was not pinged for `PING_INTERVAL`.
+pub(crate) fn ping_clients(clients: &Mutex<Vec<Client>>) {
+    for client in clients.lock().unwrap().iter_mut() {
+        if client.last_ping.elapsed() >= PING_INTERVAL {
+            client.ping();
+        }
     }
 }
 
@@ -120,13 +393,16 @@ pub struct Monolith {
     window_id: WindowId,
     draw: nannou::Draw,
     renderer: nannou::draw::Renderer,
-    texture_capturer: wgpu::TextureCapturer,
+    /// Single sampled copy of `texture` the LED colors are read back from.
+    readback_texture: wgpu::Texture,
+    readback_reshaper: wgpu::TextureReshaper,
     led_coordinates: LedCoordinates,
     collision_detector: CollisionDetector,
     viewpoint: Arc<Mutex<Option<Point2>>>,
     time_animation: Instant,
     stripe: Vec<usize>,
     client_configs: Arc<Mutex<Vec<Client>>>,
+    readback: Readback,
     cones: Cones,
     new: Arc<Mutex<bool>>,
 }
@@ -189,7 +465,13 @@ impl LedCoordinates {
 }
 
 impl Monolith {
-    pub fn new(app: &App, window_id: WindowId) -> Monolith {
+    pub fn new(
+        app: &App,
+        window_id: WindowId,
+        recording: Option<&Path>,
+        assignments: Vec<Assignment>,
+        key: Option<Key>,
+    ) -> Monolith {
         let window = app.window(window_id).unwrap();
         let device = window.device();
         let sample_count = window.msaa_samples();
@@ -211,7 +493,20 @@ impl Monolith {
             sample_count,
             texture.sample_type(),
             sample_count,
-            Frame::TEXTURE_FORMAT,
+            nannou::Frame::TEXTURE_FORMAT,
+        );
+        let readback_texture = wgpu::TextureBuilder::new()
+            .size(texture.size())
+            .usage(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC)
+            .format(wgpu::TextureFormat::Rgba16Float)
+            .build(device);
+        let readback_reshaper = wgpu::TextureReshaper::new(
+            device,
+            &texture.view().build(),
+            sample_count,
+            texture.sample_type(),
+            1,
+            wgpu::TextureFormat::Rgba16Float,
         );
 
         let position = Arc::new(Mutex::new(Some(Point2::default())));
@@ -223,12 +518,25 @@ impl Monolith {
             motion_tracker.process_frames();
         });
 
-        let client_configs = vec![
-            Client::new("192.168.1.186:34254", 0..626),
-            Client::new("192.168.1.186:34255", 626..1310),
-            Client::new("192.168.1.219:34254", 1310..1936),
-            Client::new("192.168.1.219:34255", 1936..2620),
-        ];
+        let recording = recording.map(|path| match File::create(path).and_then(Writer::new) {
+            Ok(writer) => {
+                println!("Recording the frames sent to {}", path.display());
+                writer
+            }
+            Err(e) => {
+                eprintln!("Could not record to {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        });
+
+        let led_coordinates = LedCoordinates::new();
+        let client_configs = spawn_client_tracker(assignments, key);
+        let readback = Readback::spawn(
+            &readback_texture,
+            led_coordinates.led_2d_image[0..NUM_LEDS_TO_SEND].to_vec(),
+            Arc::clone(&client_configs),
+            recording,
+        );
 
         Monolith {
             window_id,
@@ -236,13 +544,15 @@ impl Monolith {
             texture,
             renderer,
             texture_reshaper,
-            texture_capturer: wgpu::TextureCapturer::default(),
-            led_coordinates: LedCoordinates::new(),
+            readback_texture,
+            readback_reshaper,
+            led_coordinates,
             collision_detector: CollisionDetector::new(),
             viewpoint: position,
             time_animation: Instant::now(),
             stripe: Vec::new(),
-            client_configs: Arc::new(Mutex::new(client_configs)),
+            client_configs,
+            readback,
             cones: Cones {
                 positions: vec![
                     Vector::new(3000.0, 2000.0, 0.0),
@@ -255,6 +565,26 @@ impl Monolith {
         }
     }
 
+    /// The secret shared with the clients, if the file holds one. Without it
+    /// frames and clock replies are sent untagged and any host can drive the
+    /// clients.
+    pub(crate) fn load_key(path: &Path) -> Option<Key> {
+        let secret = fs::read_to_string(path).ok()?;
+        let secret = secret.trim();
+        if secret.is_empty() {
+            return None;
+        }
+        if matches!(FRAME_ENCODING, FrameEncoding::Color) {
+            eprintln!(
+                "{} holds a secret, but frames sent as OSC colors can not be tagged",
+                path.display()
+            );
+            process::exit(2);
+        }
+        println!("Tagging frames with the secret in {}", path.display());
+        Some(Key::new(secret.as_bytes()))
+    }
+
     fn parse_led_coordinates(path: &Path) -> Vec<Point2> {
         let mut rdr = Reader::from_path(path).unwrap();
         let mut points: Vec<Point2> = Vec::new();
@@ -273,6 +603,7 @@ impl Monolith {
         #[cfg(debug_assertions)]
         self.draw_debug(update);
         self.render(window, true);
+        ping_clients(&self.client_configs);
     }
 
     fn draw(&mut self, update: &Update) {
@@ -519,6 +850,7 @@ impl Monolith {
     }
 
     fn render(&mut self, window: &Window, snapshot: bool) {
+        let rendered_at = now_micros();
         let device = window.device();
         let ce_desc = wgpu::CommandEncoderDescriptor {
             label: Some("texture renderer"),
@@ -527,52 +859,66 @@ impl Monolith {
         self.renderer
             .render_to_texture(device, &mut encoder, &self.draw, &self.texture);
         if snapshot {
-            self.snapshot(window, encoder)
+            self.snapshot(window, encoder, rendered_at)
         } else {
             window.queue().submit(Some(encoder.finish()));
         }
     }
 
-    fn snapshot(&mut self, window: &Window, mut encoder: CommandEncoder) {
+    /// Copies the texture with its 16 bit float channels into a readback
+    /// buffer, whose colors at the LED positions are sent to the clients.
+    /// Unlike `wgpu::TextureCapturer`, which converts to 8 bits, this keeps
+    /// the precision the clients dither with.
+    fn snapshot(&mut self, window: &Window, mut encoder: CommandEncoder, rendered_at: u64) {
         let device = window.device();
-        let snapshot = self
-            .texture_capturer
-            .capture(device, &mut encoder, &self.texture);
+        let view = self.readback_texture.view().build();
+        self.readback_reshaper
+            .encode_render_pass(&view, &mut encoder);
+        let Some(buffer) = self.readback.buffer(device) else {
+            window.queue().submit(Some(encoder.finish()));
+            return;
+        };
+        encoder.copy_texture_to_buffer(
+            self.readback_texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(self.readback.padded_row),
+                    rows_per_image: None,
+                },
+            },
+            self.readback_texture.extent(),
+        );
         window.queue().submit(Some(encoder.finish()));
-        let led_coordinates = self.led_coordinates.led_2d_image[0..NUM_LEDS_TO_SEND].to_vec();
-        let sender_clone = self.client_configs.clone();
-
-        snapshot
-            .read(move |result| {
-                let image = result.expect("failed to map texture memory").to_owned();
-                let addr = "/";
-                let sender = sender_clone.lock().unwrap();
-                for client in sender.iter() {
-                    let args =
-                        Monolith::get_pixels(&led_coordinates[client.led_range.clone()], &image);
-                    client.sender.send((addr, args)).ok();
-                }
+        self.readback
+            .snapshots
+            .send(Snapshot {
+                buffer,
+                rendered_at,
             })
-            .unwrap();
+            .ok();
     }
+}
 
-    fn get_pixels(
-        led_coordinates: &[Point2],
-        image: &ImageBuffer<nannou::image::Rgba<u8>, Vec<u8>>,
-    ) -> Vec<Type> {
-        let mut pixels: Vec<Type> = Vec::new();
-        for coordinate in led_coordinates {
-            let p: &[u8] = image
-                .get_pixel(coordinate.x as u32, coordinate.y as u32)
-                .channels();
-            let color = Type::Color(Color {
-                red: p[0],
-                green: p[1],
-                blue: p[2],
-                alpha: p[3],
-            });
-            pixels.push(color);
-        }
-        pixels
+/// Converts the bits of a half float channel to a 16 bit sRGB encoded one,
+/// the same transfer function rendering into an 8 bit sRGB texture applies.
+fn half_to_srgb16(bits: u16) -> u16 {
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32 / 1024.0;
+    let linear = match exponent {
+        0 => mantissa * 2f32.powi(-14),
+        0x1f => 1.0,
+        _ => (1.0 + mantissa) * 2f32.powi(exponent - 15),
+    };
+    if bits & 0x8000 != 0 {
+        return 0;
     }
+    let linear = linear.min(1.0);
+    let srgb = if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 65535.0).round() as u16
 }
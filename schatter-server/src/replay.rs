@@ -0,0 +1,54 @@
+//! Plays a recording made with `schatter-server record` to the clients in a
+//! loop, without the camera, the tracking and the windows.
+
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use schatter_lib::auth::Key;
+use schatter_lib::clock::now_micros;
+use schatter_lib::recording::Player;
+
+use crate::discovery::Assignment;
+use crate::monolith::{self, PRESENTATION_DELAY};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+pub fn run(path: &Path, assignments: Vec<Assignment>, key: Option<Key>) -> ! {
+    let mut player = match Player::open(path) {
+        Ok(player) => player,
+        Err(e) => {
+            eprintln!("Could not replay {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    };
+    let clients = monolith::spawn_client_tracker(assignments, key);
+    println!("Replaying {}", path.display());
+    loop {
+        let now = Instant::now();
+        // The frames due together are stamped alike, so the strips of all
+        // clients still change together.
+        let rendered_at = now_micros();
+        let present_at = rendered_at + PRESENTATION_DELAY.as_micros() as u64;
+        loop {
+            let record = match player.next_due(now) {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Could not read {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            };
+            let mut clients = clients.lock().unwrap();
+            for client in clients
+                .iter_mut()
+                .filter(|client| client.stream == record.stream)
+            {
+                client.send_frame(record.frame.pixels.clone(), rendered_at, present_at);
+            }
+        }
+        monolith::ping_clients(&clients);
+        thread::sleep(POLL_INTERVAL);
+    }
+}